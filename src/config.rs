@@ -0,0 +1,166 @@
+//! Typed model of the `.config` file of a package.
+//!
+//! See more [.config](https://opam.ocaml.org/doc/Manual.html#lt-pkgname-gt-config)
+//!
+//! ```rust
+//! use opam_file_rs::config::{ConfigFile, ConfigValue};
+//! let opam = opam_file_rs::parse(r#"
+//!   opam-version: "2.0"
+//!   variables {
+//!     native: true
+//!     path: "/usr/lib/foo"
+//!     flags: ["-O2" "-g"]
+//!   }
+//!   file-depends: [["/usr/lib/libfoo.so" "md5=d41d8cd98f00b204e9800998ecf8427e"]]
+//! "#).unwrap();
+//! let mut config = ConfigFile::from_opam_file(&opam).unwrap();
+//! assert_eq!(config.get("native"), Some(&ConfigValue::Bool(true)));
+//! assert_eq!(config.file_depends[0].0, "/usr/lib/libfoo.so");
+//! config.variables.retain(|(name, _)| name != "flags");
+//! assert_eq!(
+//!   opam_file_rs::value::format_opam_file(&config.to_opam_file()),
+//!   r#"opam-version : "2.0"
+//! variables {
+//! native : true
+//! path : "/usr/lib/foo"
+//! }
+//! file-depends : [["/usr/lib/libfoo.so" "md5=d41d8cd98f00b204e9800998ecf8427e"]]
+//! "#
+//! );
+//! ```
+
+use std::fmt;
+
+use super::fields::{fields, invalid_type, section_fields, string, string_pairs, Fields};
+use super::package::PackageError;
+use super::value::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct ConfigFile {
+  /// `opam-version`
+  pub opam_version: Option<String>,
+  /// The `variables { }` section, in order.
+  pub variables: Vec<(String, ConfigValue)>,
+  /// `file-depends`: paths of files the package depends on, and their checksums.
+  pub file_depends: Vec<(String, String)>,
+  /// The fields not listed above, in order.
+  pub extra_fields: Vec<(String, Value)>,
+}
+
+/// The value of a variable defined by a package.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ConfigValue {
+  Bool(bool),
+  String(String),
+  List(Vec<String>),
+}
+
+impl fmt::Display for ConfigValue {
+  /// Lists are written as their elements separated by spaces, as when they are expanded.
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ConfigValue::Bool(b) => write!(f, "{}", b),
+      ConfigValue::String(str) => write!(f, "{}", str),
+      ConfigValue::List(lst) => write!(f, "{}", lst.join(" ")),
+    }
+  }
+}
+
+impl ConfigValue {
+  pub fn from_value(name: &str, value: &Value) -> Result<Self, PackageError> {
+    let invalid_type = |v: &Value| invalid_type(name, "a bool, a string or a list of strings", v);
+    match &value.kind {
+      ValueKind::Bool(b) => Ok(ConfigValue::Bool(*b)),
+      ValueKind::String(str) => Ok(ConfigValue::String(str.clone())),
+      ValueKind::List(lst) => lst
+        .iter()
+        .map(|v| match &v.kind {
+          ValueKind::String(str) => Ok(str.clone()),
+          _ => Err(invalid_type(v)),
+        })
+        .collect::<Result<_, _>>()
+        .map(ConfigValue::List),
+      _ => Err(invalid_type(value)),
+    }
+  }
+
+  pub fn to_value(&self) -> Value {
+    let string = |str: &String| make_value(ValueKind::String(str.clone()));
+    match self {
+      ConfigValue::Bool(b) => make_value(ValueKind::Bool(*b)),
+      ConfigValue::String(str) => string(str),
+      ConfigValue::List(lst) => make_value(ValueKind::List(lst.iter().map(string).collect())),
+    }
+  }
+}
+
+impl ConfigFile {
+  pub fn from_opam_file(file: &OpamFile) -> Result<Self, PackageError> {
+    let mut config = ConfigFile::default();
+    for (_, name, value) in fields(file)? {
+      match name {
+        "opam-version" => config.opam_version = Some(string(name, value)?),
+        "file-depends" => config.file_depends = string_pairs(name, value)?,
+        _ => config.extra_fields.push((name.to_string(), value.clone())),
+      }
+    }
+    let mut has_variables = false;
+    for item in &file.file_contents {
+      if let OpamFileItem::Section(pos, section) = item {
+        match section.section_kind.as_str() {
+          "variables" if has_variables => {
+            return Err(PackageError::DuplicateField("variables".to_string(), *pos))
+          }
+          "variables" => {
+            has_variables = true;
+            config.variables = variables(&section.section_item)?
+          }
+          kind => return Err(PackageError::UnknownSection(kind.to_string(), *pos)),
+        }
+      }
+    }
+    Ok(config)
+  }
+
+  /// An opam file with the fields that are set, in the order `opam-version`, `variables`, `file-depends`,
+  /// followed by the extra fields.
+  pub fn to_opam_file(&self) -> OpamFile {
+    let mut fields = Fields::default();
+    fields.string("opam-version", &self.opam_version);
+    let variables = self
+      .variables
+      .iter()
+      .map(|(name, value)| (name, value.to_value()));
+    fields.section("variables", variables);
+    let string = |str: &String| make_value(ValueKind::String(str.clone()));
+    let file_depends = self
+      .file_depends
+      .iter()
+      .map(|(path, checksum)| make_value(ValueKind::List(vec![string(path), string(checksum)])))
+      .collect();
+    fields.list("file-depends", file_depends);
+    fields.extra(&self.extra_fields);
+    OpamFile {
+      file_contents: fields.0,
+    }
+  }
+
+  /// The value of the variable `name` of the `variables` section.
+  pub fn get(&self, name: &str) -> Option<&ConfigValue> {
+    self
+      .variables
+      .iter()
+      .find(|(n, _)| n == name)
+      .map(|(_, value)| value)
+  }
+}
+
+/// The variables of a `variables { }` section, in order.
+pub(crate) fn variables(
+  items: &[OpamFileItem],
+) -> Result<Vec<(String, ConfigValue)>, PackageError> {
+  section_fields(items)?
+    .into_iter()
+    .map(|(_, name, value)| Ok((name.to_string(), ConfigValue::from_value(name, value)?)))
+    .collect()
+}
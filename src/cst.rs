@@ -0,0 +1,276 @@
+//! Lossless concrete syntax tree.
+//!
+//! Unlike [`value::OpamFile`](crate::value::OpamFile), a [`SyntaxTree`] keeps the exact source text of every token
+//! together with the whitespace and comments before it,
+//! so printing it with `to_string()` gives back the original input byte for byte.
+//!
+//! ```rust
+//! let src = "# maintained by hand\nversion: \"0.1.0\" (* bumped by CI *)\nname: \"foo\"\n";
+//! let mut tree = opam_file_rs::cst::parse(src).unwrap();
+//! assert_eq!(tree.to_string(), src);
+//!
+//! let version = opam_file_rs::value::Value {
+//!   kind: opam_file_rs::value::ValueKind::String("0.2.0".to_string()),
+//!   pos: (0, 0),
+//! };
+//! assert!(tree.set_variable("version", &version).unwrap());
+//! assert_eq!(
+//!   tree.to_string(),
+//!   "# maintained by hand\nversion: \"0.2.0\" (* bumped by CI *)\nname: \"foo\"\n"
+//! );
+//! ```
+
+use std::fmt;
+
+use super::lexer;
+use super::value;
+use super::OpamFileError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub enum TriviaKind {
+  /// Spaces, tabs and newlines
+  Whitespace,
+  /// `# ...` up to the end of the line, without the newline
+  LineComment,
+  /// `(* ... *)`, possibly nested
+  BlockComment,
+}
+
+/// Text that is not part of any token.
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct Trivia {
+  pub kind: TriviaKind,
+  pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct SyntaxToken {
+  /// Whitespace and comments between the previous token and this one.
+  pub leading_trivia: Vec<Trivia>,
+  /// Source text of the token, e.g. `"foo\n"` with its quotes and escapes as written.
+  pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub enum SyntaxItem {
+  /// `<ident> ":" <value>`
+  Variable {
+    name: SyntaxToken,
+    colon: SyntaxToken,
+    value: Vec<SyntaxToken>,
+  },
+  /// `<ident> [<string>] "{" <item>* "}"`
+  Section {
+    kind: SyntaxToken,
+    name: Option<SyntaxToken>,
+    lbrace: SyntaxToken,
+    items: Vec<SyntaxItem>,
+    rbrace: SyntaxToken,
+  },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct SyntaxTree {
+  pub items: Vec<SyntaxItem>,
+  /// Whitespace and comments after the last token.
+  pub trailing_trivia: Vec<Trivia>,
+}
+
+/// Parse `input` into a lossless syntax tree.
+pub fn parse(input: &str) -> Result<SyntaxTree, OpamFileError> {
+  let file = crate::parse(input)?;
+  let (tokens, trailing_trivia) = syntax_tokens(input)?;
+  let mut tokens = tokens.into_iter().peekable();
+  let items = make_items(&file.file_contents, &mut tokens);
+  Ok(SyntaxTree {
+    items,
+    trailing_trivia,
+  })
+}
+
+type PosToken = (SyntaxToken, value::Pos);
+
+type PosTokens = std::iter::Peekable<std::vec::IntoIter<PosToken>>;
+
+/// Lex `input`, attaching to each token the trivia before it.
+fn syntax_tokens(input: &str) -> Result<(Vec<PosToken>, Vec<Trivia>), OpamFileError> {
+  let chars = input.chars().collect::<Vec<_>>();
+  let tokens = match lexer::lex(input) {
+    Ok(tokens) => tokens,
+    Err((lexer::LexErrorKind::InvalidChar(c), start, end)) => {
+      return Err(OpamFileError::LexInvalidChar(c, start, end))
+    }
+    Err((lexer::LexErrorKind::Eof, _, _)) => return Err(OpamFileError::LexEof),
+  };
+  let trivia = |start: usize, end: usize| {
+    lexer::lex_trivia(&chars, start, end)
+      .into_iter()
+      .map(|(kind, start, end)| Trivia {
+        kind,
+        text: chars[start..end].iter().collect(),
+      })
+      .collect::<Vec<_>>()
+  };
+  let mut syntax_tokens = Vec::new();
+  let mut last_end = 0;
+  for (kind, start, end) in tokens {
+    if kind == lexer::TokenKind::EOF {
+      break;
+    }
+    let token = SyntaxToken {
+      leading_trivia: trivia(last_end, start),
+      text: chars[start..end].iter().collect(),
+    };
+    syntax_tokens.push((token, (start, end)));
+    last_end = end;
+  }
+  Ok((syntax_tokens, trivia(last_end, chars.len())))
+}
+
+fn make_items(items: &[value::OpamFileItem], tokens: &mut PosTokens) -> Vec<SyntaxItem> {
+  items
+    .iter()
+    .map(|item| match item {
+      value::OpamFileItem::Variable(pos, _, _) => {
+        let name = tokens.next().unwrap().0;
+        let colon = tokens.next().unwrap().0;
+        let mut value = Vec::new();
+        while let Some((token, _)) = tokens.next_if(|(_, token_pos)| token_pos.1 <= pos.1) {
+          value.push(token);
+        }
+        SyntaxItem::Variable { name, colon, value }
+      }
+      value::OpamFileItem::Section(_, section) => {
+        let kind = tokens.next().unwrap().0;
+        let name = section
+          .section_name
+          .as_ref()
+          .map(|_| tokens.next().unwrap().0);
+        let lbrace = tokens.next().unwrap().0;
+        let items = make_items(&section.section_item, tokens);
+        let rbrace = tokens.next().unwrap().0;
+        SyntaxItem::Section {
+          kind,
+          name,
+          lbrace,
+          items,
+          rbrace,
+        }
+      }
+    })
+    .collect()
+}
+
+impl SyntaxTree {
+  /// Convert to the abstract syntax tree.
+  /// The positions in the result refer to `self.to_string()`.
+  pub fn to_opam_file(&self) -> Result<value::OpamFile, OpamFileError> {
+    crate::parse(&self.to_string())
+  }
+
+  /// Find the top-level variable `name`.
+  pub fn variable(&self, name: &str) -> Option<&SyntaxItem> {
+    self
+      .items
+      .iter()
+      .find(|item| item.variable_name() == Some(name))
+  }
+
+  /// Replace the value of the top-level variable `name`, keeping everything else as it is.
+  /// Returns `Ok(false)` if there is no such variable.
+  pub fn set_variable(
+    &mut self,
+    name: &str,
+    new_value: &value::Value,
+  ) -> Result<bool, OpamFileError> {
+    let item = match self
+      .items
+      .iter_mut()
+      .find(|item| item.variable_name() == Some(name))
+    {
+      Some(item) => item,
+      None => return Ok(false),
+    };
+    let (tokens, _) = syntax_tokens(&value::value_to_string(new_value))?;
+    let mut tokens = tokens
+      .into_iter()
+      .map(|(token, _)| token)
+      .collect::<Vec<_>>();
+    if let SyntaxItem::Variable { value, .. } = item {
+      // keep the spacing between `:` and the value
+      if let (Some(first), Some(old_first)) = (tokens.first_mut(), value.first()) {
+        first.leading_trivia = old_first.leading_trivia.clone();
+      }
+      std::mem::swap(value, &mut tokens);
+    }
+    Ok(true)
+  }
+}
+
+impl SyntaxItem {
+  /// The name of the variable, or `None` for sections.
+  pub fn variable_name(&self) -> Option<&str> {
+    match self {
+      SyntaxItem::Variable { name, .. } => Some(&name.text),
+      SyntaxItem::Section { .. } => None,
+    }
+  }
+}
+
+impl fmt::Display for Trivia {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.text)
+  }
+}
+
+impl fmt::Display for SyntaxToken {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for trivia in self.leading_trivia.iter() {
+      write!(f, "{}", trivia)?;
+    }
+    write!(f, "{}", self.text)
+  }
+}
+
+impl fmt::Display for SyntaxItem {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      SyntaxItem::Variable { name, colon, value } => {
+        write!(f, "{}{}", name, colon)?;
+        for token in value.iter() {
+          write!(f, "{}", token)?;
+        }
+        Ok(())
+      }
+      SyntaxItem::Section {
+        kind,
+        name,
+        lbrace,
+        items,
+        rbrace,
+      } => {
+        write!(f, "{}", kind)?;
+        if let Some(name) = name {
+          write!(f, "{}", name)?;
+        }
+        write!(f, "{}", lbrace)?;
+        for item in items.iter() {
+          write!(f, "{}", item)?;
+        }
+        write!(f, "{}", rbrace)
+      }
+    }
+  }
+}
+
+impl fmt::Display for SyntaxTree {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for item in self.items.iter() {
+      write!(f, "{}", item)?;
+    }
+    for trivia in self.trailing_trivia.iter() {
+      write!(f, "{}", trivia)?;
+    }
+    Ok(())
+  }
+}
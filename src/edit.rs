@@ -0,0 +1,267 @@
+//! Format-preserving editing.
+//!
+//! Edits are applied as small textual replacements on the original source,
+//! located with the [`Pos`](crate::value::Pos) of the items and values,
+//! so the layout and comments of everything that is not edited are kept as they are.
+//!
+//! ```rust
+//! use opam_file_rs::edit::Editor;
+//! use opam_file_rs::value::{Value, ValueKind};
+//!
+//! let src = "opam-version: \"2.0\"\nversion: \"0.1.0\" # bumped by CI\ndepends: [\n  \"dune\"\n]\n";
+//! let mut editor = Editor::new(src).unwrap();
+//! let string = |s: &str| Value { kind: ValueKind::String(s.to_string()), pos: (0, 0) };
+//! editor.set_field("version", &string("0.2.0")).unwrap();
+//! editor.insert_list_element("depends", 1, &string("ocaml")).unwrap();
+//! assert_eq!(
+//!   editor.source(),
+//!   "opam-version: \"2.0\"\nversion: \"0.2.0\" # bumped by CI\ndepends: [\n  \"dune\"\n  \"ocaml\"\n]\n"
+//! );
+//! ```
+
+use super::value;
+use super::OpamFileError;
+
+/// Replace the text at `pos` with `text`.
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct TextEdit {
+  /// Char positions in the source, as in [`value::Pos`].
+  pub pos: value::Pos,
+  pub text: String,
+}
+
+/// Apply `edits` to `source`.
+/// The edits must not overlap; they are applied as if simultaneously, so all positions refer to `source`.
+pub fn apply_edits(source: &str, edits: &[TextEdit]) -> String {
+  let mut edits = edits.iter().collect::<Vec<_>>();
+  edits.sort_by_key(|edit| edit.pos);
+  let chars = source.chars().collect::<Vec<_>>();
+  let mut str = String::new();
+  let mut pos = 0;
+  for edit in edits {
+    let start = edit.pos.0.min(chars.len());
+    str.extend(chars[pos.min(start)..start].iter());
+    str.push_str(&edit.text);
+    pos = pos.max(edit.pos.1.min(chars.len()));
+  }
+  str.extend(chars[pos..].iter());
+  str
+}
+
+/// An opam file source being edited.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Editor {
+  source: String,
+  file: value::OpamFile,
+}
+
+impl Editor {
+  pub fn new(source: &str) -> Result<Self, OpamFileError> {
+    let file = crate::parse(source)?;
+    Ok(Editor {
+      source: source.to_string(),
+      file,
+    })
+  }
+
+  /// The current source, with all edits applied.
+  pub fn source(&self) -> &str {
+    &self.source
+  }
+
+  /// The current source parsed. Positions refer to [`Editor::source`].
+  pub fn file(&self) -> &value::OpamFile {
+    &self.file
+  }
+
+  /// Apply `edits`, which refer to the current source.
+  /// If the result is not a valid opam file, nothing is changed and the parse error is returned.
+  pub fn apply(&mut self, edits: &[TextEdit]) -> Result<(), OpamFileError> {
+    let source = apply_edits(&self.source, edits);
+    self.file = crate::parse(&source)?;
+    self.source = source;
+    Ok(())
+  }
+
+  /// Replace the text at `pos` (e.g. the `pos` of a [`value::Value`] in [`Editor::file`]) with `new_value`.
+  pub fn replace_value(
+    &mut self,
+    pos: value::Pos,
+    new_value: &value::Value,
+  ) -> Result<(), OpamFileError> {
+    self.apply(&[TextEdit {
+      pos,
+      text: value::value_to_string(new_value),
+    }])
+  }
+
+  /// Set the value of the top-level field `name`.
+  /// An existing field is changed in place; otherwise it is appended after the last item.
+  pub fn set_field(&mut self, name: &str, new_value: &value::Value) -> Result<(), OpamFileError> {
+    let text = value::value_to_string(new_value);
+    if let Some((_, value)) = self.field(name) {
+      let pos = value.pos;
+      return self.apply(&[TextEdit { pos, text }]);
+    }
+    let edit = match self.file.file_contents.last() {
+      Some(last) => {
+        let pos = item_pos(last);
+        let chars = self.source.chars().collect::<Vec<_>>();
+        let end = end_of_line(&chars, pos.1).unwrap_or(pos.1);
+        TextEdit {
+          pos: (end, end),
+          text: format!("\n{}{}: {}", self.indentation(pos.0), name, text),
+        }
+      }
+      None => {
+        let end = self.source.chars().count();
+        TextEdit {
+          pos: (end, end),
+          text: format!("{}: {}\n", name, text),
+        }
+      }
+    };
+    self.apply(&[edit])
+  }
+
+  /// Remove the top-level field `name`.
+  /// If the field is alone on its lines, the lines are removed along with a trailing `#` comment.
+  /// Returns `Ok(false)` if there is no such field.
+  pub fn remove_field(&mut self, name: &str) -> Result<bool, OpamFileError> {
+    let pos = match self.field(name) {
+      Some((pos, _)) => pos,
+      None => return Ok(false),
+    };
+    let chars = self.source.chars().collect::<Vec<_>>();
+    let line_start = chars[..pos.0]
+      .iter()
+      .rposition(|c| *c == '\n')
+      .map(|i| i + 1)
+      .unwrap_or(0);
+    let alone = chars[line_start..pos.0]
+      .iter()
+      .all(|c| matches!(c, ' ' | '\t'));
+    let pos = match end_of_line(&chars, pos.1) {
+      Some(end) if alone => (line_start, (end + 1).min(chars.len())),
+      _ => {
+        let mut end = pos.1;
+        while end < chars.len() && matches!(chars[end], ' ' | '\t') {
+          end += 1;
+        }
+        (pos.0, end)
+      }
+    };
+    self.apply(&[TextEdit {
+      pos,
+      text: String::new(),
+    }])?;
+    Ok(true)
+  }
+
+  /// Insert `new_value` at `index` in the list that is the value of the top-level field `name`.
+  /// The new element is separated from its neighbours the same way the existing elements are.
+  /// Returns `Ok(false)` if there is no such field or its value is not a list.
+  pub fn insert_list_element(
+    &mut self,
+    name: &str,
+    index: usize,
+    new_value: &value::Value,
+  ) -> Result<bool, OpamFileError> {
+    let (list_pos, elements) = match self.field(name) {
+      Some((
+        _,
+        value::Value {
+          kind: value::ValueKind::List(elements),
+          pos,
+        },
+      )) => (*pos, elements.clone()),
+      _ => return Ok(false),
+    };
+    let text = value::value_to_string(new_value);
+    let chars = self.source.chars().collect::<Vec<_>>();
+    let slice = |start: usize, end: usize| chars[start..end].iter().collect::<String>();
+    let edit = match elements.as_slice() {
+      [] => TextEdit {
+        pos: list_pos,
+        text: format!("[{}]", text),
+      },
+      [first, ..] => {
+        // one element per line (keeping the indentation but not comments), or all on one line
+        let gap = match elements.as_slice() {
+          [_, second, ..] => slice(first.pos.1, second.pos.0),
+          _ => slice(list_pos.0 + 1, first.pos.0),
+        };
+        let separator = match gap.rfind('\n') {
+          Some(i) => format!("\n{}", &gap[i + 1..]),
+          None => " ".to_string(),
+        };
+        match elements.get(index) {
+          Some(element) => {
+            let start = element.pos.0;
+            TextEdit {
+              pos: (start, start),
+              text: format!("{}{}", text, separator),
+            }
+          }
+          None => {
+            let end = elements.last().unwrap().pos.1;
+            TextEdit {
+              pos: (end, end),
+              text: format!("{}{}", separator, text),
+            }
+          }
+        }
+      }
+    };
+    self.apply(&[edit])?;
+    Ok(true)
+  }
+
+  fn field(&self, name: &str) -> Option<(value::Pos, &value::Value)> {
+    self.file.file_contents.iter().find_map(|item| match item {
+      value::OpamFileItem::Variable(pos, n, value) if n == name => Some((*pos, value)),
+      _ => None,
+    })
+  }
+
+  /// The whitespace between the start of the line and `pos`, if there is nothing else.
+  fn indentation(&self, pos: usize) -> String {
+    let chars = self.source.chars().collect::<Vec<_>>();
+    let line_start = chars[..pos]
+      .iter()
+      .rposition(|c| *c == '\n')
+      .map(|i| i + 1)
+      .unwrap_or(0);
+    let indentation = &chars[line_start..pos];
+    if indentation.iter().all(|c| matches!(c, ' ' | '\t')) {
+      indentation.iter().collect()
+    } else {
+      String::new()
+    }
+  }
+}
+
+/// If only whitespace and a `#` comment follow `pos` on its line, the position of the end of the line.
+fn end_of_line(chars: &[char], pos: usize) -> Option<usize> {
+  let mut end = pos;
+  while end < chars.len() && matches!(chars[end], ' ' | '\t' | '\r') {
+    end += 1;
+  }
+  if chars.get(end) == Some(&'#') {
+    while end < chars.len() && chars[end] != '\n' {
+      end += 1;
+    }
+  }
+  if end == chars.len() || chars[end] == '\n' {
+    Some(end)
+  } else {
+    None
+  }
+}
+
+fn item_pos(item: &value::OpamFileItem) -> value::Pos {
+  match item {
+    value::OpamFileItem::Section(pos, _) => *pos,
+    value::OpamFileItem::Variable(pos, _, _) => *pos,
+  }
+}
@@ -0,0 +1,308 @@
+//! Reading the fields of an opam file, and writing them back, for the typed models of its kinds of files.
+
+use super::formula::PackageFormula;
+use super::package::{Argument, ArgumentKind, Command, EnvUpdate, FilteredString, PackageError};
+use super::value::*;
+
+/// The top-level fields of `file` with their positions, checking that none is duplicated.
+pub(crate) fn fields(file: &OpamFile) -> Result<Vec<(Pos, &str, &Value)>, PackageError> {
+  let mut fields: Vec<(Pos, &str, &Value)> = Vec::new();
+  for item in &file.file_contents {
+    if let OpamFileItem::Variable(pos, name, value) = item {
+      if fields.iter().any(|(_, n, _)| n == name) {
+        return Err(PackageError::DuplicateField(name.clone(), *pos));
+      }
+      fields.push((*pos, name, value));
+    }
+  }
+  Ok(fields)
+}
+
+/// The fields of a section with their positions, which must not have duplicated fields nor nested sections.
+pub(crate) fn section_fields(
+  items: &[OpamFileItem],
+) -> Result<Vec<(Pos, &str, &Value)>, PackageError> {
+  let mut fields: Vec<(Pos, &str, &Value)> = Vec::new();
+  for item in items {
+    match item {
+      OpamFileItem::Variable(pos, name, value) => {
+        if fields.iter().any(|(_, n, _)| n == name) {
+          return Err(PackageError::DuplicateField(name.clone(), *pos));
+        }
+        fields.push((*pos, name, value));
+      }
+      OpamFileItem::Section(pos, section) => {
+        return Err(PackageError::UnknownSection(
+          section.section_kind.clone(),
+          *pos,
+        ))
+      }
+    }
+  }
+  Ok(fields)
+}
+
+/// Check that `file` has no section.
+pub(crate) fn no_sections(file: &OpamFile) -> Result<(), PackageError> {
+  for item in &file.file_contents {
+    if let OpamFileItem::Section(pos, section) = item {
+      return Err(PackageError::UnknownSection(
+        section.section_kind.clone(),
+        *pos,
+      ));
+    }
+  }
+  Ok(())
+}
+
+pub(crate) fn invalid_type(field: &str, expected: &'static str, value: &Value) -> PackageError {
+  PackageError::InvalidType(
+    field.to_string(),
+    expected,
+    value_to_string(value),
+    value.pos,
+  )
+}
+
+pub(crate) fn string(field: &str, value: &Value) -> Result<String, PackageError> {
+  match &value.kind {
+    ValueKind::String(str) => Ok(str.clone()),
+    _ => Err(invalid_type(field, "a string", value)),
+  }
+}
+
+/// A list of strings, or a single string.
+pub(crate) fn strings(field: &str, value: &Value) -> Result<Vec<String>, PackageError> {
+  match &value.kind {
+    ValueKind::String(str) => Ok(vec![str.clone()]),
+    ValueKind::List(lst) => lst
+      .iter()
+      .map(|v| string(field, v).map_err(|_| invalid_type(field, "a list of strings", v)))
+      .collect(),
+    _ => Err(invalid_type(field, "a list of strings", value)),
+  }
+}
+
+pub(crate) fn int(field: &str, value: &Value) -> Result<usize, PackageError> {
+  match &value.kind {
+    ValueKind::Int(i) if *i >= 0 => Ok(*i as usize),
+    _ => Err(invalid_type(field, "a non-negative integer", value)),
+  }
+}
+
+pub(crate) fn bool(field: &str, value: &Value) -> Result<bool, PackageError> {
+  match &value.kind {
+    ValueKind::Bool(b) => Ok(*b),
+    _ => Err(invalid_type(field, "a bool", value)),
+  }
+}
+
+/// The contents of `{ }`, which must be a single filter.
+fn filter(field: &str, filters: &[Value]) -> Result<Option<Value>, PackageError> {
+  match filters {
+    [] => Ok(None),
+    [filter] => Ok(Some(filter.clone())),
+    [_, second, ..] => Err(invalid_type(field, "a single filter", second)),
+  }
+}
+
+/// `value` and its filter, if it is followed by `{ }`.
+pub(crate) fn split_option<'a>(
+  field: &str,
+  value: &'a Value,
+) -> Result<(&'a Value, Option<Value>), PackageError> {
+  match &value.kind {
+    ValueKind::Option(v, filters) => Ok((v, filter(field, filters)?)),
+    _ => Ok((value, None)),
+  }
+}
+
+/// A list of strings with optional filters, or a single one.
+pub(crate) fn filtered_strings(
+  field: &str,
+  value: &Value,
+) -> Result<Vec<FilteredString>, PackageError> {
+  let filtered_string = |v: &Value| {
+    let (v, filter) = split_option(field, v)?;
+    match &v.kind {
+      ValueKind::String(str) => Ok(FilteredString {
+        value: str.clone(),
+        filter,
+      }),
+      _ => Err(invalid_type(field, "a list of strings with filters", v)),
+    }
+  };
+  match &value.kind {
+    ValueKind::List(lst) => lst.iter().map(filtered_string).collect(),
+    _ => Ok(vec![filtered_string(value)?]),
+  }
+}
+
+/// A list of commands, or a single command.
+pub(crate) fn commands(field: &str, value: &Value) -> Result<Vec<Command>, PackageError> {
+  let is_command = |v: &Value| match &v.kind {
+    ValueKind::List(_) => true,
+    ValueKind::Option(v, _) => matches!(v.kind, ValueKind::List(_)),
+    _ => false,
+  };
+  match &value.kind {
+    ValueKind::List(lst) if lst.iter().all(is_command) => {
+      lst.iter().map(|v| command(field, v)).collect()
+    }
+    _ => Ok(vec![command(field, value)?]),
+  }
+}
+
+/// `[args] {filter}`, or a list of arguments given directly as the value of the field.
+pub(crate) fn command(field: &str, value: &Value) -> Result<Command, PackageError> {
+  let (args, filter) = split_option(field, value)?;
+  let args = match &args.kind {
+    ValueKind::List(args) => args,
+    _ => return Err(invalid_type(field, "a list of commands", value)),
+  };
+  let args = args
+    .iter()
+    .map(|arg| {
+      let (arg, filter) = split_option(field, arg)?;
+      let kind = match &arg.kind {
+        ValueKind::String(str) => ArgumentKind::String(str.clone()),
+        ValueKind::Ident(str) => ArgumentKind::Ident(str.clone()),
+        _ => return Err(invalid_type(field, "a list of commands", arg)),
+      };
+      Ok(Argument { kind, filter })
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+  Ok(Command { args, filter })
+}
+
+/// A list of `VAR op "value"`, or a single one.
+pub(crate) fn env_updates(field: &str, value: &Value) -> Result<Vec<EnvUpdate>, PackageError> {
+  let env_update = |v: &Value| match &v.kind {
+    ValueKind::EnvBinding(l, op, r) => match (&l.kind, &r.kind) {
+      (ValueKind::Ident(variable), ValueKind::String(str)) => Ok(EnvUpdate {
+        variable: variable.clone(),
+        op: op.kind.clone(),
+        value: str.clone(),
+      }),
+      _ => Err(invalid_type(field, "a list of environment updates", v)),
+    },
+    _ => Err(invalid_type(field, "a list of environment updates", v)),
+  };
+  match &value.kind {
+    ValueKind::List(lst) => lst.iter().map(env_update).collect(),
+    _ => Ok(vec![env_update(value)?]),
+  }
+}
+
+/// A list of `[string string]`, or a single one.
+pub(crate) fn string_pairs(
+  field: &str,
+  value: &Value,
+) -> Result<Vec<(String, String)>, PackageError> {
+  let pair = |v: &Value| match &v.kind {
+    ValueKind::List(pair) => match pair.as_slice() {
+      [a, b] => Ok((string(field, a)?, string(field, b)?)),
+      _ => Err(invalid_type(field, "a list of pairs of strings", v)),
+    },
+    _ => Err(invalid_type(field, "a list of pairs of strings", v)),
+  };
+  match &value.kind {
+    ValueKind::List(lst) if lst.iter().all(|v| matches!(v.kind, ValueKind::List(_))) => {
+      lst.iter().map(pair).collect()
+    }
+    _ => Ok(vec![pair(value)?]),
+  }
+}
+
+/// The items of a file being written, skipping the fields that are not set.
+#[derive(Default)]
+pub(crate) struct Fields(pub(crate) Vec<OpamFileItem>);
+
+impl Fields {
+  pub(crate) fn push(&mut self, name: &str, value: Value) {
+    self
+      .0
+      .push(OpamFileItem::Variable((0, 0), name.to_string(), value));
+  }
+
+  pub(crate) fn string(&mut self, name: &str, str: &Option<String>) {
+    if let Some(str) = str {
+      self.push(name, make_value(ValueKind::String(str.clone())));
+    }
+  }
+
+  pub(crate) fn strings(&mut self, name: &str, strs: &[String]) {
+    if !strs.is_empty() {
+      self.push(name, strings_value(strs));
+    }
+  }
+
+  pub(crate) fn int(&mut self, name: &str, i: Option<usize>) {
+    if let Some(i) = i {
+      self.push(name, make_value(ValueKind::Int(i as isize)));
+    }
+  }
+
+  pub(crate) fn bool(&mut self, name: &str, b: Option<bool>) {
+    if let Some(b) = b {
+      self.push(name, make_value(ValueKind::Bool(b)));
+    }
+  }
+
+  pub(crate) fn command(&mut self, name: &str, command: &Option<Command>) {
+    if let Some(command) = command {
+      self.push(name, command.to_value());
+    }
+  }
+
+  pub(crate) fn commands(&mut self, name: &str, commands: &[Command]) {
+    self.list(name, commands.iter().map(Command::to_value).collect());
+  }
+
+  pub(crate) fn formula(&mut self, name: &str, formula: &Option<PackageFormula>) {
+    if let Some(formula) = formula {
+      self.push(name, formula.to_value());
+    }
+  }
+
+  pub(crate) fn list(&mut self, name: &str, lst: Vec<Value>) {
+    if !lst.is_empty() {
+      self.push(name, make_value(ValueKind::List(lst)));
+    }
+  }
+
+  pub(crate) fn extra(&mut self, fields: &[(String, Value)]) {
+    for (name, value) in fields {
+      self.push(name, value.clone());
+    }
+  }
+
+  pub(crate) fn section<'a>(
+    &mut self,
+    kind: &str,
+    fields: impl Iterator<Item = (&'a String, Value)>,
+  ) {
+    let section_item = fields
+      .map(|(name, value)| OpamFileItem::Variable((0, 0), name.clone(), value))
+      .collect::<Vec<_>>();
+    if !section_item.is_empty() {
+      self.0.push(OpamFileItem::Section(
+        (0, 0),
+        OpamFileSection {
+          section_kind: kind.to_string(),
+          section_name: None,
+          section_item,
+        },
+      ));
+    }
+  }
+}
+
+pub(crate) fn strings_value(strs: &[String]) -> Value {
+  make_value(ValueKind::List(
+    strs
+      .iter()
+      .map(|str| make_value(ValueKind::String(str.clone())))
+      .collect(),
+  ))
+}
@@ -0,0 +1,359 @@
+//! Evaluation of filters.
+//!
+//! See more [Filters](https://opam.ocaml.org/doc/Manual.html#Filters)
+//!
+//! Variables are looked up with a [`Resolver`].
+//! A variable it does not know is undefined, and so is any comparison or operator applied to it,
+//! except that `false & x` is `false`, `true | x` is `true`, and `?x` tells whether `x` is defined.
+//! Comparisons use the [version ordering](crate::version).
+//! Strings are [expanded](crate::interpolation::expand) before being used.
+//!
+//! ```rust
+//! use opam_file_rs::filter::*;
+//! let opam = opam_file_rs::parse(r#"available: os = "linux" & ocaml:version >= "4.10""#).unwrap();
+//! let available = match &opam.file_contents[0] {
+//!   opam_file_rs::value::OpamFileItem::Variable(_, _, value) => value,
+//!   _ => unreachable!(),
+//! };
+//! let resolver = |scope: Scope, name: &str| match (scope, name) {
+//!   (Scope::Global, "os") => Some(VariableValue::String("linux".to_string())),
+//!   (Scope::Package("ocaml"), "version") => Some(VariableValue::String("4.14.1".to_string())),
+//!   _ => None,
+//! };
+//! assert_eq!(eval(available, &resolver), Ok(FilterValue::Bool(true)));
+//! assert_eq!(eval(available, &|_: Scope, _: &str| None), Ok(FilterValue::Undefined));
+//! ```
+
+use thiserror::Error;
+
+use super::interpolation;
+use super::value::*;
+use super::version;
+
+/// Where a variable is looked up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub enum Scope<'a> {
+  /// `name`
+  Global,
+  /// `pkg:name`
+  Package(&'a str),
+  /// `_:name`, the package the filter belongs to
+  Current,
+}
+
+/// The contents of a defined variable.
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub enum VariableValue {
+  Bool(bool),
+  String(String),
+}
+
+/// Looks up variables, returning `None` for an undefined one.
+pub trait Resolver {
+  fn resolve(&self, scope: Scope, name: &str) -> Option<VariableValue>;
+}
+
+impl<F> Resolver for F
+where
+  F: Fn(Scope, &str) -> Option<VariableValue>,
+{
+  fn resolve(&self, scope: Scope, name: &str) -> Option<VariableValue> {
+    self(scope, name)
+  }
+}
+
+/// The result of a filter.
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub enum FilterValue {
+  Bool(bool),
+  String(String),
+  Undefined,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Error)]
+pub enum EvalError {
+  /// A value that cannot appear in a filter, such as a list or `>= "1.0"` without a left operand.
+  #[error("not a filter: `{0}` at {}", .1.0)]
+  NotAFilter(String, Pos),
+  /// An operand of `&`, `|` or `!`, or a variable of several packages, that is not a boolean.
+  #[error("expected a boolean, found `{0}` at {}", .1.0)]
+  NotABool(String, Pos),
+  #[error("invalid variable `{0}` at {}", .1.0)]
+  InvalidVariable(String, Pos),
+  /// A malformed `%{ }%` in the string at the given position.
+  #[error("{0} in the string at {}", .1.0)]
+  Interpolation(interpolation::InterpolationError, Pos),
+}
+
+/// Evaluate `filter`.
+pub fn eval(filter: &Value, resolver: &impl Resolver) -> Result<FilterValue, EvalError> {
+  match &filter.kind {
+    ValueKind::Bool(b) => Ok(FilterValue::Bool(*b)),
+    ValueKind::Int(i) => Ok(FilterValue::String(i.to_string())),
+    ValueKind::String(str) => interpolation::expand(str, resolver)
+      .map(FilterValue::String)
+      .map_err(|err| EvalError::Interpolation(err, filter.pos)),
+    ValueKind::Ident(name) => eval_variable(name, filter.pos, resolver),
+    ValueKind::Group(lst) if lst.len() == 1 => eval(&lst[0], resolver),
+    ValueKind::RelOp(op, l, r) => {
+      match (to_string(eval(l, resolver)?), to_string(eval(r, resolver)?)) {
+        (Some(l), Some(r)) => Ok(FilterValue::Bool(compare(filter, &op.kind, &l, &r)?)),
+        _ => Ok(FilterValue::Undefined),
+      }
+    }
+    ValueKind::LogOp(op, l, r) => {
+      let l = to_bool(eval(l, resolver)?, l)?;
+      let r = to_bool(eval(r, resolver)?, r)?;
+      let b = match op.kind {
+        LogOpKind::And => and(l, r),
+        LogOpKind::Or => match (l, r) {
+          (Some(true), _) | (_, Some(true)) => Some(true),
+          (Some(false), Some(false)) => Some(false),
+          _ => None,
+        },
+      };
+      Ok(b.map_or(FilterValue::Undefined, FilterValue::Bool))
+    }
+    ValueKind::PfxOp(op, r) => {
+      let v = eval(r, resolver)?;
+      match op.kind {
+        PfxOpKind::Not => {
+          Ok(to_bool(v, r)?.map_or(FilterValue::Undefined, |b| FilterValue::Bool(!b)))
+        }
+        PfxOpKind::Defined => Ok(FilterValue::Bool(v != FilterValue::Undefined)),
+      }
+    }
+    _ => Err(not_a_filter(filter)),
+  }
+}
+
+/// Evaluate `filter` to a boolean, or `None` if it is undefined.
+pub fn eval_to_bool(filter: &Value, resolver: &impl Resolver) -> Result<Option<bool>, EvalError> {
+  to_bool(eval(filter, resolver)?, filter)
+}
+
+/// Simplify `filter` with the variables `resolver` knows, keeping the others as they are.
+///
+/// Comparisons of known values are computed, `true &` and `false |` are removed,
+/// and `false &` and `true |` make the whole operation constant.
+/// A filter whose variables are all known is simplified to a boolean or a string.
+///
+/// ```rust
+/// use opam_file_rs::filter::*;
+/// let opam = opam_file_rs::parse(r#"available: os = "linux" & (with-test | arch != "arm64")"#).unwrap();
+/// let available = match &opam.file_contents[0] {
+///   opam_file_rs::value::OpamFileItem::Variable(_, _, value) => value,
+///   _ => unreachable!(),
+/// };
+/// let resolver = |scope: Scope, name: &str| match (scope, name) {
+///   (Scope::Global, "os") => Some(VariableValue::String("linux".to_string())),
+///   (Scope::Global, "arch") => Some(VariableValue::String("arm64".to_string())),
+///   _ => None,
+/// };
+/// let residual = simplify(available, &resolver).unwrap();
+/// assert_eq!(residual.kind, opam_file_rs::value::ValueKind::Ident("with-test".to_string()));
+/// ```
+pub fn simplify(filter: &Value, resolver: &impl Resolver) -> Result<Value, EvalError> {
+  Ok(match partial_eval(filter, resolver)? {
+    Partial::Known(v) => known_to_value(v, filter),
+    // parentheses around the whole result are not needed
+    Partial::Unknown(Value {
+      kind: ValueKind::Group(lst),
+      ..
+    }) if lst.len() == 1 => lst.into_iter().next().unwrap(),
+    Partial::Unknown(v) => v,
+  })
+}
+
+enum Partial {
+  /// A boolean or a string.
+  Known(FilterValue),
+  Unknown(Value),
+}
+
+fn partial_eval(filter: &Value, resolver: &impl Resolver) -> Result<Partial, EvalError> {
+  let unknown = |kind| {
+    Ok(Partial::Unknown(Value {
+      kind,
+      pos: filter.pos,
+    }))
+  };
+  let to_value = |partial, value| match partial {
+    Partial::Known(v) => Box::new(known_to_value(v, value)),
+    Partial::Unknown(v) => Box::new(v),
+  };
+  match &filter.kind {
+    ValueKind::Bool(_) | ValueKind::Int(_) | ValueKind::String(_) | ValueKind::Ident(_) => {
+      let v = eval(filter, resolver)?;
+      if v == FilterValue::Undefined || !is_known_string(filter, resolver) {
+        Ok(Partial::Unknown(filter.clone()))
+      } else {
+        Ok(Partial::Known(v))
+      }
+    }
+    ValueKind::Group(lst) if lst.len() == 1 => match partial_eval(&lst[0], resolver)? {
+      // the parentheses are only kept around what is left of an operation
+      Partial::Unknown(v) if matches!(v.kind, ValueKind::LogOp(..)) => {
+        unknown(ValueKind::Group(vec![v]))
+      }
+      partial => Ok(partial),
+    },
+    ValueKind::RelOp(op, l, r) => match (partial_eval(l, resolver)?, partial_eval(r, resolver)?) {
+      (Partial::Known(lv), Partial::Known(rv)) => {
+        let (lv, rv) = (to_string(lv).unwrap(), to_string(rv).unwrap());
+        Ok(Partial::Known(FilterValue::Bool(compare(
+          filter, &op.kind, &lv, &rv,
+        )?)))
+      }
+      (lp, rp) => unknown(ValueKind::RelOp(
+        op.clone(),
+        to_value(lp, l),
+        to_value(rp, r),
+      )),
+    },
+    ValueKind::LogOp(op, l, r) => {
+      let absorbing = op.kind == LogOpKind::Or;
+      let known_bool = |partial: Partial, value: &Value| match partial {
+        Partial::Known(v) => Ok(Ok(to_bool(v, value)?.unwrap())),
+        Partial::Unknown(v) => Ok(Err(v)),
+      };
+      match (
+        known_bool(partial_eval(l, resolver)?, l)?,
+        known_bool(partial_eval(r, resolver)?, r)?,
+      ) {
+        (Ok(b), _) | (_, Ok(b)) if b == absorbing => Ok(Partial::Known(FilterValue::Bool(b))),
+        (Ok(b), Ok(_)) => Ok(Partial::Known(FilterValue::Bool(b))),
+        (Ok(_), Err(v)) | (Err(v), Ok(_)) => Ok(Partial::Unknown(v)),
+        (Err(lv), Err(rv)) => unknown(ValueKind::LogOp(op.clone(), Box::new(lv), Box::new(rv))),
+      }
+    }
+    ValueKind::PfxOp(op, r) => match (&op.kind, partial_eval(r, resolver)?) {
+      (PfxOpKind::Not, Partial::Known(v)) => {
+        Ok(Partial::Known(FilterValue::Bool(!to_bool(v, r)?.unwrap())))
+      }
+      (PfxOpKind::Defined, Partial::Known(_)) => Ok(Partial::Known(FilterValue::Bool(true))),
+      (_, Partial::Unknown(v)) => unknown(ValueKind::PfxOp(op.clone(), Box::new(v))),
+    },
+    _ => Err(not_a_filter(filter)),
+  }
+}
+
+/// Whether all the interpolations in the string `filter`, if it is one, are known.
+fn is_known_string(filter: &Value, resolver: &impl Resolver) -> bool {
+  match &filter.kind {
+    ValueKind::String(str) => interpolation::parse(str)
+      .map(|segments| {
+        segments.iter().all(|segment| match segment {
+          interpolation::Segment::Literal(_) => true,
+          interpolation::Segment::Variable(var) => !matches!(
+            resolve_variable(var, resolver),
+            Err(_) | Ok(FilterValue::Undefined)
+          ),
+        })
+      })
+      .unwrap_or(true),
+    _ => true,
+  }
+}
+
+/// The known value `v` of `filter`. Integers are left as they are written.
+fn known_to_value(v: FilterValue, filter: &Value) -> Value {
+  if let ValueKind::Int(_) = filter.kind {
+    return filter.clone();
+  }
+  let kind = match v {
+    FilterValue::Bool(b) => ValueKind::Bool(b),
+    FilterValue::String(str) => ValueKind::String(str),
+    FilterValue::Undefined => unreachable!("an undefined value is not known"),
+  };
+  Value {
+    kind,
+    pos: filter.pos,
+  }
+}
+
+fn eval_variable(name: &str, pos: Pos, resolver: &impl Resolver) -> Result<FilterValue, EvalError> {
+  let var = interpolation::parse_variable(name, pos)
+    .map_err(|_| EvalError::InvalidVariable(name.to_string(), pos))?;
+  resolve_variable(&var, resolver)
+}
+
+/// The value of `var`, ignoring its `?then:else` part.
+pub(crate) fn resolve_variable(
+  var: &interpolation::VariableRef,
+  resolver: &impl Resolver,
+) -> Result<FilterValue, EvalError> {
+  let from_variable = |v: Option<VariableValue>| match v {
+    Some(VariableValue::Bool(b)) => FilterValue::Bool(b),
+    Some(VariableValue::String(str)) => FilterValue::String(str),
+    None => FilterValue::Undefined,
+  };
+  match var.packages.as_slice() {
+    [] => Ok(from_variable(resolver.resolve(Scope::Global, &var.name))),
+    [package] => Ok(from_variable(resolver.resolve(scope(package), &var.name))),
+    // `pkg1+pkg2:name` is true if `name` is true for all of the packages
+    packages => {
+      let mut result = Some(true);
+      for package in packages {
+        let v = from_variable(resolver.resolve(scope(package), &var.name));
+        let b = match v {
+          FilterValue::Bool(b) => Some(b),
+          FilterValue::String(str) => match str.as_str() {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => return Err(EvalError::NotABool(str, var.pos)),
+          },
+          FilterValue::Undefined => None,
+        };
+        result = and(result, b);
+      }
+      Ok(result.map_or(FilterValue::Undefined, FilterValue::Bool))
+    }
+  }
+}
+
+fn scope(package: &str) -> Scope<'_> {
+  if package == "_" {
+    Scope::Current
+  } else {
+    Scope::Package(package)
+  }
+}
+
+fn and(l: Option<bool>, r: Option<bool>) -> Option<bool> {
+  match (l, r) {
+    (Some(false), _) | (_, Some(false)) => Some(false),
+    (Some(true), Some(true)) => Some(true),
+    _ => None,
+  }
+}
+
+/// `"true"` and `"false"` are read as booleans, as opam does.
+fn to_bool(v: FilterValue, filter: &Value) -> Result<Option<bool>, EvalError> {
+  match v {
+    FilterValue::Bool(b) => Ok(Some(b)),
+    FilterValue::String(str) if str == "true" => Ok(Some(true)),
+    FilterValue::String(str) if str == "false" => Ok(Some(false)),
+    FilterValue::String(_) => Err(EvalError::NotABool(value_to_string(filter), filter.pos)),
+    FilterValue::Undefined => Ok(None),
+  }
+}
+
+fn compare(filter: &Value, op: &RelOpKind, l: &str, r: &str) -> Result<bool, EvalError> {
+  match op {
+    RelOpKind::Sem => Err(not_a_filter(filter)),
+    op => Ok(version::relop_holds(op, version::compare(l, r))),
+  }
+}
+
+fn to_string(v: FilterValue) -> Option<String> {
+  match v {
+    FilterValue::Bool(b) => Some(b.to_string()),
+    FilterValue::String(str) => Some(str),
+    FilterValue::Undefined => None,
+  }
+}
+
+fn not_a_filter(value: &Value) -> EvalError {
+  EvalError::NotAFilter(value_to_string(value), value.pos)
+}
@@ -0,0 +1,724 @@
+//! Typed formulas.
+//!
+//! See more [Package Formulas](https://opam.ocaml.org/doc/Manual.html#Package-Formulas)
+//!
+//! A [`VersionFormula`] is a constraint on versions, like the contents of `{ }` after a package:
+//!
+//! ```rust
+//! use opam_file_rs::formula::VersionFormula;
+//! use opam_file_rs::value::{OpamFileItem, RelOpKind, ValueKind};
+//! let opam = opam_file_rs::parse(r#"depends: ["foo" {>= "1.2" & < "2.0"}]"#).unwrap();
+//! let constraint = match &opam.file_contents[0] {
+//!   OpamFileItem::Variable(_, _, value) => match &value.kind {
+//!     ValueKind::List(lst) => match &lst[0].kind {
+//!       ValueKind::Option(_, constraint) => constraint.clone(),
+//!       _ => unreachable!(),
+//!     },
+//!     _ => unreachable!(),
+//!   },
+//!   _ => unreachable!(),
+//! };
+//! let formula = VersionFormula::from_values(&constraint).unwrap();
+//! assert!(formula.matches(&"1.9.3".parse().unwrap()));
+//! assert!(formula.matches(&"2.0~beta".parse().unwrap()));
+//! assert!(!formula.matches(&"2.0.1".parse().unwrap()));
+//! let newer = VersionFormula::Atom(RelOpKind::Geq, "2.1".parse().unwrap());
+//! assert!(formula.intersection(&newer).is_empty());
+//! assert_eq!(formula.union(&newer).to_string(), r#">= "1.2" & < "2.0" | >= "2.1""#);
+//! ```
+//!
+//! A [`PackageFormula`] is a formula over packages, like the value of `depends:`:
+//!
+//! ```rust
+//! use opam_file_rs::formula::{DependencyFlag, PackageFormula};
+//! let opam = opam_file_rs::parse(r#"depends: [
+//!   "ocaml" {>= "4.08"}
+//!   "dune" {build}
+//!   ("lwt" | "async")
+//!   "alcotest" {with-test & os != "win32"}
+//! ]"#).unwrap();
+//! let depends = match &opam.file_contents[0] {
+//!   opam_file_rs::value::OpamFileItem::Variable(_, _, value) => value,
+//!   _ => unreachable!(),
+//! };
+//! let formula = PackageFormula::from_value(depends).unwrap();
+//! assert_eq!(formula.package_names(), ["ocaml", "dune", "lwt", "async", "alcotest"]);
+//! let alcotest = formula.atoms()[4];
+//! assert_eq!(alcotest.flags, [DependencyFlag::WithTest]);
+//! assert!(formula.to_value().eq_ignoring_positions(depends));
+//! ```
+
+use std::cmp::Ordering;
+use std::fmt;
+use thiserror::Error;
+
+use super::filter::{self, FilterValue, Resolver, Scope, VariableValue};
+use super::value::*;
+use super::version::{self, OpamVersion, VersionError};
+
+/// A constraint on the version of a package, like `>= "1.2" & < "2.0"`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum VersionFormula {
+  /// No constraint, written as nothing in `{ }`.
+  Any,
+  /// `<relop> <version>`. The relop is never [`RelOpKind::Sem`].
+  Atom(RelOpKind, OpamVersion),
+  And(Box<VersionFormula>, Box<VersionFormula>),
+  Or(Box<VersionFormula>, Box<VersionFormula>),
+}
+
+/// A formula over packages, like the value of `depends:`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PackageFormula {
+  Atom(PackageAtom),
+  /// All of the formulas, like the elements of the list of `depends:` or `a & b`. Empty if there is no dependency.
+  And(Vec<PackageFormula>),
+  /// One of the formulas, like `a | b`.
+  Or(Vec<PackageFormula>),
+}
+
+/// A package with the conditions in the `{ }` following it.
+///
+/// The conditions are the conjunction of `flags`, `version` and `filter`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PackageAtom {
+  pub name: String,
+  pub flags: Vec<DependencyFlag>,
+  pub version: VersionFormula,
+  /// The other conditions, with the `&` between them, e.g. `os = "linux"` or `with-test | >= "2.0"`.
+  pub filter: Option<Value>,
+}
+
+/// A variable restricting when a dependency applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub enum DependencyFlag {
+  /// `build`
+  Build,
+  /// `post`
+  Post,
+  /// `with-test`
+  WithTest,
+  /// `with-doc`
+  WithDoc,
+  /// `with-dev-setup`
+  WithDevSetup,
+  /// `dev`
+  Dev,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Error)]
+pub enum FormulaError {
+  #[error("not a version formula: `{0}` at {}", .1.0)]
+  NotAVersionFormula(String, Pos),
+  #[error("not a package formula: `{0}` at {}", .1.0)]
+  NotAPackageFormula(String, Pos),
+  #[error("{0} at {}", .1.0)]
+  InvalidVersion(VersionError, Pos),
+  #[error("{0}")]
+  Eval(#[from] filter::EvalError),
+  /// The normal form would have more clauses than the limit.
+  #[error("the normal form has more than {0} clauses")]
+  TooLarge(usize),
+}
+
+impl VersionFormula {
+  /// Convert a formula made of `<relop> <version>`, `&`, `|` and parentheses.
+  pub fn from_value(value: &Value) -> Result<Self, FormulaError> {
+    let not_a_formula = || FormulaError::NotAVersionFormula(value_to_string(value), value.pos);
+    match &value.kind {
+      ValueKind::PrefixRelOp(op, v) => match (&op.kind, &v.kind) {
+        (RelOpKind::Sem, _) => Err(not_a_formula()),
+        (kind, ValueKind::String(str)) => {
+          let version = str
+            .parse()
+            .map_err(|err| FormulaError::InvalidVersion(err, v.pos))?;
+          Ok(VersionFormula::Atom(kind.clone(), version))
+        }
+        _ => Err(not_a_formula()),
+      },
+      ValueKind::LogOp(op, l, r) => {
+        let l = Box::new(VersionFormula::from_value(l)?);
+        let r = Box::new(VersionFormula::from_value(r)?);
+        match op.kind {
+          LogOpKind::And => Ok(VersionFormula::And(l, r)),
+          LogOpKind::Or => Ok(VersionFormula::Or(l, r)),
+        }
+      }
+      ValueKind::Group(lst) => VersionFormula::from_values(lst),
+      _ => Err(not_a_formula()),
+    }
+  }
+
+  /// Convert the contents of `{ }`, whose values are all required.
+  pub fn from_values(values: &[Value]) -> Result<Self, FormulaError> {
+    values
+      .iter()
+      .try_fold(VersionFormula::Any, |formula, value| {
+        Ok(formula.intersection(&VersionFormula::from_value(value)?))
+      })
+  }
+
+  /// The formula as written in `{ }`, with positions `(0, 0)`; `None` for [`VersionFormula::Any`].
+  pub fn to_value(&self) -> Option<Value> {
+    match self {
+      VersionFormula::Any => None,
+      VersionFormula::Atom(kind, version) => Some(make_value(ValueKind::PrefixRelOp(
+        RelOp {
+          kind: kind.clone(),
+          pos: (0, 0),
+        },
+        Box::new(make_value(ValueKind::String(version.to_string()))),
+      ))),
+      VersionFormula::And(l, r) | VersionFormula::Or(l, r) => {
+        let kind = match self {
+          VersionFormula::And(_, _) => LogOpKind::And,
+          _ => LogOpKind::Or,
+        };
+        let (l, r) = match (l.to_value(), r.to_value()) {
+          (Some(l), Some(r)) => (l, r),
+          (l, r) => return l.or(r),
+        };
+        Some(logop_value(kind, l, r))
+      }
+    }
+  }
+
+  pub fn matches(&self, version: &OpamVersion) -> bool {
+    match self {
+      VersionFormula::Any => true,
+      VersionFormula::Atom(kind, v) => version::relop_holds(kind, version.cmp(v)),
+      VersionFormula::And(l, r) => l.matches(version) && r.matches(version),
+      VersionFormula::Or(l, r) => l.matches(version) || r.matches(version),
+    }
+  }
+
+  /// The versions matching both formulas.
+  pub fn intersection(&self, other: &VersionFormula) -> VersionFormula {
+    match (self, other) {
+      (VersionFormula::Any, f) | (f, VersionFormula::Any) => f.clone(),
+      (l, r) => VersionFormula::And(Box::new(l.clone()), Box::new(r.clone())),
+    }
+  }
+
+  /// The versions matching either formula.
+  pub fn union(&self, other: &VersionFormula) -> VersionFormula {
+    match (self, other) {
+      (VersionFormula::Any, _) | (_, VersionFormula::Any) => VersionFormula::Any,
+      (l, r) => VersionFormula::Or(Box::new(l.clone()), Box::new(r.clone())),
+    }
+  }
+
+  /// Whether no version matches the formula.
+  ///
+  /// Between any two different versions there is another one (e.g. `1.0` < `1.0a` < `1.0.0`),
+  /// so only contradictory bounds, like `> "2.0" & < "1.0"` or `>= "1.0" & <= "1.0" & != "1.0"`, are empty.
+  pub fn is_empty(&self) -> bool {
+    !self.disjuncts().iter().any(|atoms| is_satisfiable(atoms))
+  }
+
+  /// The formula as a disjunction of conjunctions of atoms.
+  fn disjuncts(&self) -> Vec<Vec<(&RelOpKind, &OpamVersion)>> {
+    match self {
+      VersionFormula::Any => vec![vec![]],
+      VersionFormula::Atom(kind, version) => vec![vec![(kind, version)]],
+      VersionFormula::Or(l, r) => {
+        let mut disjuncts = l.disjuncts();
+        disjuncts.extend(r.disjuncts());
+        disjuncts
+      }
+      VersionFormula::And(l, r) => {
+        let r = r.disjuncts();
+        l.disjuncts()
+          .into_iter()
+          .flat_map(|l_atoms| {
+            r.iter().map(move |r_atoms| {
+              let mut atoms = l_atoms.clone();
+              atoms.extend(r_atoms.iter().cloned());
+              atoms
+            })
+          })
+          .collect()
+      }
+    }
+  }
+}
+
+impl PackageFormula {
+  /// Convert a formula such as the value of `depends:` or `depopts:`.
+  pub fn from_value(value: &Value) -> Result<Self, FormulaError> {
+    match &value.kind {
+      // a list is a conjunction even with a single element, so that elements can be added to it
+      ValueKind::List(lst) => match PackageFormula::from_values(lst)? {
+        PackageFormula::And(lst) => Ok(PackageFormula::And(lst)),
+        formula => Ok(PackageFormula::And(vec![formula])),
+      },
+      _ => PackageFormula::from_element(value),
+    }
+  }
+
+  fn from_values(values: &[Value]) -> Result<Self, FormulaError> {
+    let formulas = values
+      .iter()
+      .map(PackageFormula::from_element)
+      .collect::<Result<Vec<_>, _>>()?;
+    Ok(PackageFormula::and(formulas))
+  }
+
+  fn from_element(value: &Value) -> Result<Self, FormulaError> {
+    let not_a_formula = || FormulaError::NotAPackageFormula(value_to_string(value), value.pos);
+    match &value.kind {
+      ValueKind::String(name) => Ok(PackageFormula::Atom(PackageAtom::new(name))),
+      ValueKind::Option(v, conditions) => match &v.kind {
+        ValueKind::String(name) => Ok(PackageFormula::Atom(PackageAtom::from_conditions(
+          name, conditions,
+        )?)),
+        _ => Err(not_a_formula()),
+      },
+      ValueKind::LogOp(op, l, r) => {
+        let formulas = vec![
+          PackageFormula::from_element(l)?,
+          PackageFormula::from_element(r)?,
+        ];
+        match op.kind {
+          LogOpKind::And => Ok(PackageFormula::and(formulas)),
+          LogOpKind::Or => Ok(PackageFormula::or(formulas)),
+        }
+      }
+      ValueKind::Group(lst) => PackageFormula::from_values(lst),
+      _ => Err(not_a_formula()),
+    }
+  }
+
+  /// `formulas` joined with `&`, flattening nested conjunctions.
+  fn and(formulas: Vec<PackageFormula>) -> PackageFormula {
+    let mut flat = Vec::new();
+    for formula in formulas {
+      match formula {
+        PackageFormula::And(lst) => flat.extend(lst),
+        formula => flat.push(formula),
+      }
+    }
+    if flat.len() == 1 {
+      flat.pop().unwrap()
+    } else {
+      PackageFormula::And(flat)
+    }
+  }
+
+  /// `formulas` joined with `|`, flattening nested disjunctions.
+  fn or(formulas: Vec<PackageFormula>) -> PackageFormula {
+    let mut flat = Vec::new();
+    for formula in formulas {
+      match formula {
+        PackageFormula::Or(lst) => flat.extend(lst),
+        formula => flat.push(formula),
+      }
+    }
+    if flat.len() == 1 {
+      flat.pop().unwrap()
+    } else {
+      PackageFormula::Or(flat)
+    }
+  }
+
+  /// The formula as the list written after `depends:`, with positions `(0, 0)`.
+  pub fn to_value(&self) -> Value {
+    let formulas = match self {
+      PackageFormula::And(lst) => lst.iter().collect(),
+      formula => vec![formula],
+    };
+    // alternatives are parenthesized to set them apart from the other elements
+    let elements = formulas
+      .into_iter()
+      .map(|formula| match formula {
+        PackageFormula::Or(_) => make_value(ValueKind::Group(vec![formula.to_element()])),
+        _ => formula.to_element(),
+      })
+      .collect();
+    make_value(ValueKind::List(elements))
+  }
+
+  fn to_element(&self) -> Value {
+    match self {
+      PackageFormula::Atom(atom) => atom.to_value(),
+      PackageFormula::And(lst) | PackageFormula::Or(lst) => {
+        let kind = match self {
+          PackageFormula::And(_) => LogOpKind::And,
+          _ => LogOpKind::Or,
+        };
+        let mut values = lst.iter().map(|f| f.to_element());
+        match values.next() {
+          Some(first) => values.fold(first, |l, r| logop_value(kind.clone(), l, r)),
+          None => make_value(ValueKind::Group(vec![])),
+        }
+      }
+    }
+  }
+
+  /// All the atoms, in order.
+  pub fn atoms(&self) -> Vec<&PackageAtom> {
+    match self {
+      PackageFormula::Atom(atom) => vec![atom],
+      PackageFormula::And(lst) | PackageFormula::Or(lst) => {
+        lst.iter().flat_map(|f| f.atoms()).collect()
+      }
+    }
+  }
+
+  /// The names of all the packages, in order of first appearance.
+  pub fn package_names(&self) -> Vec<&str> {
+    let mut names: Vec<&str> = Vec::new();
+    for atom in self.atoms() {
+      if !names.contains(&atom.name.as_str()) {
+        names.push(&atom.name);
+      }
+    }
+    names
+  }
+}
+
+impl PackageFormula {
+  /// Evaluate the flags and filters of the atoms, as opam does before solving.
+  ///
+  /// Flags are looked up as global variables, e.g. `build` and `with-test`, and are false if undefined.
+  /// An atom whose conditions are not all true is removed, as are the formulas left empty:
+  /// if `with-test` is false, `"a" {with-test} | "b"` becomes `"b"`, and `"a" {with-test}` becomes empty.
+  /// A version given as a variable, like `= _:version`, is resolved into the version constraint,
+  /// and so are the filters mixed with versions: `"a" {with-test | >= "2.0"}` becomes `"a"`
+  /// if `with-test` is true, and `"a" {>= "2.0"}` otherwise.
+  /// The atoms of the result have only a version constraint.
+  pub fn eval_conditions(&self, resolver: &impl Resolver) -> Result<PackageFormula, FormulaError> {
+    Ok(
+      self
+        .eval_aux(resolver)?
+        .unwrap_or(PackageFormula::And(vec![])),
+    )
+  }
+
+  /// `None` if nothing is left of the formula.
+  fn eval_aux(&self, resolver: &impl Resolver) -> Result<Option<PackageFormula>, FormulaError> {
+    match self {
+      PackageFormula::Atom(atom) => Ok(atom.eval_conditions(resolver)?.map(PackageFormula::Atom)),
+      PackageFormula::And(lst) | PackageFormula::Or(lst) => {
+        let mut formulas = Vec::new();
+        for formula in lst {
+          formulas.extend(formula.eval_aux(resolver)?);
+        }
+        Ok(match (self, formulas.is_empty()) {
+          (_, true) => None,
+          (PackageFormula::And(_), false) => Some(PackageFormula::and(formulas)),
+          (_, false) => Some(PackageFormula::or(formulas)),
+        })
+      }
+    }
+  }
+
+  /// The conjunctive normal form: a list of clauses which must all hold, each a list of alternatives.
+  /// An error is returned if there would be more than `max_clauses` clauses.
+  pub fn to_cnf(&self, max_clauses: usize) -> Result<Vec<Vec<PackageAtom>>, FormulaError> {
+    normal_form(self, true, max_clauses)
+  }
+
+  /// The disjunctive normal form: a list of alternatives, each a list of atoms which must all hold.
+  /// An error is returned if there would be more than `max_clauses` alternatives.
+  pub fn to_dnf(&self, max_clauses: usize) -> Result<Vec<Vec<PackageAtom>>, FormulaError> {
+    normal_form(self, false, max_clauses)
+  }
+}
+
+/// The clauses of the CNF if `cnf`, or of the DNF otherwise.
+/// The operation listed at the top of the normal form (`&` for the CNF) concatenates the clauses,
+/// and the other one takes their product.
+fn normal_form(
+  formula: &PackageFormula,
+  cnf: bool,
+  max_clauses: usize,
+) -> Result<Vec<Vec<PackageAtom>>, FormulaError> {
+  match formula {
+    PackageFormula::Atom(atom) => Ok(vec![vec![atom.clone()]]),
+    PackageFormula::And(lst) | PackageFormula::Or(lst) => {
+      let concat = matches!(formula, PackageFormula::And(_)) == cnf;
+      let mut clauses = if concat { vec![] } else { vec![vec![]] };
+      for formula in lst {
+        let sub = normal_form(formula, cnf, max_clauses)?;
+        if concat {
+          for clause in sub {
+            if !contains_clause(&clauses, &clause) {
+              clauses.push(clause);
+            }
+          }
+        } else {
+          if clauses.len().saturating_mul(sub.len()) > max_clauses {
+            return Err(FormulaError::TooLarge(max_clauses));
+          }
+          let mut product = Vec::new();
+          for clause in &clauses {
+            for sub_clause in &sub {
+              let mut clause = clause.clone();
+              for atom in sub_clause {
+                if !clause.iter().any(|a| a.eq_ignoring_positions(atom)) {
+                  clause.push(atom.clone());
+                }
+              }
+              if !contains_clause(&product, &clause) {
+                product.push(clause);
+              }
+            }
+          }
+          clauses = product;
+        }
+        if clauses.len() > max_clauses {
+          return Err(FormulaError::TooLarge(max_clauses));
+        }
+      }
+      Ok(clauses)
+    }
+  }
+}
+
+fn contains_clause(clauses: &[Vec<PackageAtom>], clause: &[PackageAtom]) -> bool {
+  clauses.iter().any(|c| {
+    c.len() == clause.len()
+      && c
+        .iter()
+        .zip(clause)
+        .all(|(a, b)| a.eq_ignoring_positions(b))
+  })
+}
+
+impl PackageAtom {
+  /// A package without conditions.
+  pub fn new(name: &str) -> Self {
+    PackageAtom {
+      name: name.to_string(),
+      flags: Vec::new(),
+      version: VersionFormula::Any,
+      filter: None,
+    }
+  }
+
+  /// Sort the conjuncts of the `{ }` after `name` into flags, version constraints and other filters.
+  fn from_conditions(name: &str, conditions: &[Value]) -> Result<Self, FormulaError> {
+    let mut conjuncts = Vec::new();
+    for condition in conditions {
+      conjuncts_of(condition, &mut conjuncts);
+    }
+    let mut atom = PackageAtom::new(name);
+    for conjunct in conjuncts {
+      if let ValueKind::Ident(ident) = &conjunct.kind {
+        if let Some(flag) = DependencyFlag::from_ident(ident) {
+          atom.flags.push(flag);
+          continue;
+        }
+      }
+      match VersionFormula::from_value(conjunct) {
+        Ok(version) => atom.version = atom.version.intersection(&version),
+        // a version given as a variable, like `= _:version`, is a filter
+        Err(FormulaError::NotAVersionFormula(_, _)) => {
+          atom.filter = Some(match atom.filter {
+            Some(filter) => logop_value(LogOpKind::And, filter, conjunct.clone()),
+            None => conjunct.clone(),
+          })
+        }
+        Err(err) => return Err(err),
+      }
+    }
+    Ok(atom)
+  }
+
+  /// Compare the atoms ignoring the positions in their filters.
+  pub fn eq_ignoring_positions(&self, other: &PackageAtom) -> bool {
+    self.name == other.name
+      && self.flags == other.flags
+      && self.version == other.version
+      && match (&self.filter, &other.filter) {
+        (Some(l), Some(r)) => l.eq_ignoring_positions(r),
+        (l, r) => l.is_none() && r.is_none(),
+      }
+  }
+
+  /// `"name" {conditions}`, or `"name"` if there is no condition.
+  pub fn to_value(&self) -> Value {
+    let mut conditions = self
+      .flags
+      .iter()
+      .map(|flag| make_value(ValueKind::Ident(flag.as_str().to_string())))
+      .collect::<Vec<_>>();
+    conditions.extend(self.version.to_value());
+    conditions.extend(self.filter.clone());
+    let name = make_value(ValueKind::String(self.name.clone()));
+    let mut conditions = conditions.into_iter();
+    match conditions.next() {
+      Some(first) => {
+        let condition = conditions.fold(first, |l, r| logop_value(LogOpKind::And, l, r));
+        make_value(ValueKind::Option(Box::new(name), vec![condition]))
+      }
+      None => name,
+    }
+  }
+}
+
+impl PackageAtom {
+  /// The atom with its flags and filter evaluated into its version constraint,
+  /// or `None` if they are not all true.
+  fn eval_conditions(&self, resolver: &impl Resolver) -> Result<Option<PackageAtom>, FormulaError> {
+    for flag in &self.flags {
+      let enabled = match resolver.resolve(Scope::Global, flag.as_str()) {
+        Some(VariableValue::Bool(b)) => b,
+        Some(VariableValue::String(str)) => str == "true",
+        None => false,
+      };
+      if !enabled {
+        return Ok(None);
+      }
+    }
+    let mut version = self.version.clone();
+    if let Some(filter) = &self.filter {
+      match eval_condition(filter, resolver)? {
+        Some(filter_version) => version = version.intersection(&filter_version),
+        None => return Ok(None),
+      }
+    }
+    Ok(Some(PackageAtom {
+      name: self.name.clone(),
+      flags: Vec::new(),
+      version,
+      filter: None,
+    }))
+  }
+}
+
+/// The versions `condition` allows, or `None` if it is not true.
+///
+/// A `<relop> <filter>` is a version constraint, any other operand of `&` and `|` a filter,
+/// which allows any version if it is true.
+fn eval_condition(
+  condition: &Value,
+  resolver: &impl Resolver,
+) -> Result<Option<VersionFormula>, FormulaError> {
+  match &condition.kind {
+    ValueKind::LogOp(op, l, r) => {
+      let (l, r) = (eval_condition(l, resolver)?, eval_condition(r, resolver)?);
+      Ok(match (&op.kind, l, r) {
+        (LogOpKind::And, Some(l), Some(r)) => Some(l.intersection(&r)),
+        (LogOpKind::And, _, _) => None,
+        (LogOpKind::Or, Some(l), Some(r)) => Some(l.union(&r)),
+        (LogOpKind::Or, l, r) => l.or(r),
+      })
+    }
+    ValueKind::Group(lst) if lst.len() == 1 => eval_condition(&lst[0], resolver),
+    ValueKind::PrefixRelOp(op, v) => match filter::eval(v, resolver)? {
+      FilterValue::String(str) => Ok(Some(VersionFormula::Atom(
+        op.kind.clone(),
+        str
+          .parse()
+          .map_err(|err| FormulaError::InvalidVersion(err, v.pos))?,
+      ))),
+      FilterValue::Bool(_) => Err(FormulaError::NotAVersionFormula(
+        value_to_string(condition),
+        condition.pos,
+      )),
+      FilterValue::Undefined => Ok(None),
+    },
+    _ => Ok(match filter::eval_to_bool(condition, resolver)? {
+      Some(true) => Some(VersionFormula::Any),
+      _ => None,
+    }),
+  }
+}
+
+/// Push the operands of the `&`s at the top of `value`.
+fn conjuncts_of<'a>(value: &'a Value, conjuncts: &mut Vec<&'a Value>) {
+  match &value.kind {
+    ValueKind::LogOp(op, l, r) if op.kind == LogOpKind::And => {
+      conjuncts_of(l, conjuncts);
+      conjuncts_of(r, conjuncts);
+    }
+    ValueKind::Group(lst) if lst.len() == 1 => conjuncts_of(&lst[0], conjuncts),
+    _ => conjuncts.push(value),
+  }
+}
+
+impl DependencyFlag {
+  pub fn from_ident(ident: &str) -> Option<Self> {
+    match ident {
+      "build" => Some(DependencyFlag::Build),
+      "post" => Some(DependencyFlag::Post),
+      "with-test" => Some(DependencyFlag::WithTest),
+      "with-doc" => Some(DependencyFlag::WithDoc),
+      "with-dev-setup" => Some(DependencyFlag::WithDevSetup),
+      "dev" => Some(DependencyFlag::Dev),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      DependencyFlag::Build => "build",
+      DependencyFlag::Post => "post",
+      DependencyFlag::WithTest => "with-test",
+      DependencyFlag::WithDoc => "with-doc",
+      DependencyFlag::WithDevSetup => "with-dev-setup",
+      DependencyFlag::Dev => "dev",
+    }
+  }
+}
+
+impl fmt::Display for VersionFormula {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.to_value() {
+      Some(value) => write!(f, "{}", value_to_string(&value)),
+      None => Ok(()),
+    }
+  }
+}
+
+/// Whether some version satisfies all of `atoms`.
+fn is_satisfiable(atoms: &[(&RelOpKind, &OpamVersion)]) -> bool {
+  // the tightest bounds, with whether they are inclusive
+  let mut lower: Option<(&OpamVersion, bool)> = None;
+  let mut upper: Option<(&OpamVersion, bool)> = None;
+  for (kind, version) in atoms {
+    match kind {
+      RelOpKind::Eq => {
+        lower = tighter(lower, (version, true), Ordering::Greater);
+        upper = tighter(upper, (version, true), Ordering::Less);
+      }
+      RelOpKind::Geq => lower = tighter(lower, (version, true), Ordering::Greater),
+      RelOpKind::Gt => lower = tighter(lower, (version, false), Ordering::Greater),
+      RelOpKind::Leq => upper = tighter(upper, (version, true), Ordering::Less),
+      RelOpKind::Lt => upper = tighter(upper, (version, false), Ordering::Less),
+      RelOpKind::Neq => (),
+      RelOpKind::Sem => return false,
+    }
+  }
+  match (lower, upper) {
+    (Some((lo, lo_inclusive)), Some((hi, hi_inclusive))) => match lo.cmp(hi) {
+      Ordering::Less => true,
+      Ordering::Greater => false,
+      // a single version, which must not be excluded
+      Ordering::Equal => {
+        lo_inclusive
+          && hi_inclusive
+          && !atoms
+            .iter()
+            .any(|(kind, version)| **kind == RelOpKind::Neq && *version == lo)
+      }
+    },
+    _ => true,
+  }
+}
+
+/// The stricter of `bound` and `new`, `ord` being how a stricter version compares to the other.
+fn tighter<'a>(
+  bound: Option<(&'a OpamVersion, bool)>,
+  new: (&'a OpamVersion, bool),
+  ord: Ordering,
+) -> Option<(&'a OpamVersion, bool)> {
+  match bound {
+    Some((v, inclusive)) if v.cmp(new.0) == ord || (v == new.0 && !inclusive) => {
+      Some((v, inclusive))
+    }
+    _ => Some(new),
+  }
+}
@@ -0,0 +1,293 @@
+//! Typed model of the `.install` file.
+//!
+//! See more [.install](https://opam.ocaml.org/doc/Manual.html#lt-pkgname-gt-install)
+//!
+//! ```rust
+//! use std::path::{Path, PathBuf};
+//! use opam_file_rs::install::{InstallFile, InstallSection};
+//! let opam = opam_file_rs::parse(r#"
+//!   lib: ["_build/foo.cma" {"foo.cma"} "?_build/foo.cmxs"]
+//!   bin: ["_build/main.exe" {"foo"}]
+//!   man: ["doc/foo.1"]
+//! "#).unwrap();
+//! let install = InstallFile::from_opam_file(&opam).unwrap();
+//! assert!(install.lib[1].optional);
+//! assert_eq!(
+//!   install.install_paths(Path::new("/usr/local"), "foo"),
+//!   [
+//!     (InstallSection::Lib, &install.lib[0], PathBuf::from("/usr/local/lib/foo/foo.cma")),
+//!     (InstallSection::Lib, &install.lib[1], PathBuf::from("/usr/local/lib/foo/foo.cmxs")),
+//!     (InstallSection::Bin, &install.bin[0], PathBuf::from("/usr/local/bin/foo")),
+//!     (InstallSection::Man, &install.man[0], PathBuf::from("/usr/local/man/man1/foo.1")),
+//!   ]
+//! );
+//! ```
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use super::fields::{fields, invalid_type, no_sections};
+use super::package::PackageError;
+use super::value::*;
+
+/// The sections of an `.install` file, each a list of files to install.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct InstallFile {
+  pub lib: Vec<InstallEntry>,
+  /// `lib_root`
+  pub lib_root: Vec<InstallEntry>,
+  pub libexec: Vec<InstallEntry>,
+  /// `libexec_root`
+  pub libexec_root: Vec<InstallEntry>,
+  pub bin: Vec<InstallEntry>,
+  pub sbin: Vec<InstallEntry>,
+  pub toplevel: Vec<InstallEntry>,
+  pub share: Vec<InstallEntry>,
+  /// `share_root`
+  pub share_root: Vec<InstallEntry>,
+  pub etc: Vec<InstallEntry>,
+  pub doc: Vec<InstallEntry>,
+  pub stublibs: Vec<InstallEntry>,
+  pub man: Vec<InstallEntry>,
+  /// Files installed outside of the prefix; their destinations are absolute paths.
+  pub misc: Vec<InstallEntry>,
+}
+
+/// A file to install, like `"?_build/foo.cmxs" {"foo.cmxs"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct InstallEntry {
+  /// The path of the file, relative to the build directory.
+  pub src: String,
+  /// Whether `src` starts with `?`, i.e. the file is skipped if it does not exist.
+  pub optional: bool,
+  /// The destination, relative to the directory of the section, if the file is renamed.
+  pub dst: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstallSection {
+  Lib,
+  LibRoot,
+  Libexec,
+  LibexecRoot,
+  Bin,
+  Sbin,
+  Toplevel,
+  Share,
+  ShareRoot,
+  Etc,
+  Doc,
+  Stublibs,
+  Man,
+  Misc,
+}
+
+impl InstallSection {
+  pub const ALL: [InstallSection; 14] = [
+    InstallSection::Lib,
+    InstallSection::LibRoot,
+    InstallSection::Libexec,
+    InstallSection::LibexecRoot,
+    InstallSection::Bin,
+    InstallSection::Sbin,
+    InstallSection::Toplevel,
+    InstallSection::Share,
+    InstallSection::ShareRoot,
+    InstallSection::Etc,
+    InstallSection::Doc,
+    InstallSection::Stublibs,
+    InstallSection::Man,
+    InstallSection::Misc,
+  ];
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    InstallSection::ALL
+      .iter()
+      .find(|section| section.as_str() == name)
+      .copied()
+  }
+
+  /// The name of the field in the `.install` file.
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      InstallSection::Lib => "lib",
+      InstallSection::LibRoot => "lib_root",
+      InstallSection::Libexec => "libexec",
+      InstallSection::LibexecRoot => "libexec_root",
+      InstallSection::Bin => "bin",
+      InstallSection::Sbin => "sbin",
+      InstallSection::Toplevel => "toplevel",
+      InstallSection::Share => "share",
+      InstallSection::ShareRoot => "share_root",
+      InstallSection::Etc => "etc",
+      InstallSection::Doc => "doc",
+      InstallSection::Stublibs => "stublibs",
+      InstallSection::Man => "man",
+      InstallSection::Misc => "misc",
+    }
+  }
+
+  /// The directory the files of the section are installed in, for the package `name`.
+  ///
+  /// `misc` has no directory, and gives `prefix`.
+  pub fn dir(&self, prefix: &Path, name: &str) -> PathBuf {
+    match self {
+      InstallSection::Lib | InstallSection::Libexec => prefix.join("lib").join(name),
+      InstallSection::LibRoot | InstallSection::LibexecRoot => prefix.join("lib"),
+      InstallSection::Bin => prefix.join("bin"),
+      InstallSection::Sbin => prefix.join("sbin"),
+      InstallSection::Toplevel => prefix.join("lib").join("toplevel"),
+      InstallSection::Share => prefix.join("share").join(name),
+      InstallSection::ShareRoot => prefix.join("share"),
+      InstallSection::Etc => prefix.join("etc").join(name),
+      InstallSection::Doc => prefix.join("doc").join(name),
+      InstallSection::Stublibs => prefix.join("lib").join("stublibs"),
+      InstallSection::Man => prefix.join("man"),
+      InstallSection::Misc => prefix.to_path_buf(),
+    }
+  }
+}
+
+impl fmt::Display for InstallSection {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.as_str())
+  }
+}
+
+impl InstallFile {
+  pub fn from_opam_file(file: &OpamFile) -> Result<Self, PackageError> {
+    let mut install = InstallFile::default();
+    for (pos, name, value) in fields(file)? {
+      let section = InstallSection::from_name(name)
+        .ok_or_else(|| PackageError::UnknownField(name.to_string(), pos))?;
+      let entries = entries(name, value)?;
+      if section == InstallSection::Misc {
+        let relative = entries.iter().find(|entry| {
+          !entry
+            .dst
+            .as_ref()
+            .is_some_and(|dst| Path::new(dst).has_root())
+        });
+        if let Some(entry) = relative {
+          return Err(PackageError::MissingDestination(
+            entry.src.clone(),
+            value.pos,
+          ));
+        }
+      }
+      *install.section_mut(section) = entries;
+    }
+    no_sections(file)?;
+    Ok(install)
+  }
+
+  pub fn section(&self, section: InstallSection) -> &Vec<InstallEntry> {
+    match section {
+      InstallSection::Lib => &self.lib,
+      InstallSection::LibRoot => &self.lib_root,
+      InstallSection::Libexec => &self.libexec,
+      InstallSection::LibexecRoot => &self.libexec_root,
+      InstallSection::Bin => &self.bin,
+      InstallSection::Sbin => &self.sbin,
+      InstallSection::Toplevel => &self.toplevel,
+      InstallSection::Share => &self.share,
+      InstallSection::ShareRoot => &self.share_root,
+      InstallSection::Etc => &self.etc,
+      InstallSection::Doc => &self.doc,
+      InstallSection::Stublibs => &self.stublibs,
+      InstallSection::Man => &self.man,
+      InstallSection::Misc => &self.misc,
+    }
+  }
+
+  pub fn section_mut(&mut self, section: InstallSection) -> &mut Vec<InstallEntry> {
+    match section {
+      InstallSection::Lib => &mut self.lib,
+      InstallSection::LibRoot => &mut self.lib_root,
+      InstallSection::Libexec => &mut self.libexec,
+      InstallSection::LibexecRoot => &mut self.libexec_root,
+      InstallSection::Bin => &mut self.bin,
+      InstallSection::Sbin => &mut self.sbin,
+      InstallSection::Toplevel => &mut self.toplevel,
+      InstallSection::Share => &mut self.share,
+      InstallSection::ShareRoot => &mut self.share_root,
+      InstallSection::Etc => &mut self.etc,
+      InstallSection::Doc => &mut self.doc,
+      InstallSection::Stublibs => &mut self.stublibs,
+      InstallSection::Man => &mut self.man,
+      InstallSection::Misc => &mut self.misc,
+    }
+  }
+
+  /// The path each file is installed at, for the package `name` installed in `prefix`, in the order of the sections.
+  pub fn install_paths(
+    &self,
+    prefix: &Path,
+    name: &str,
+  ) -> Vec<(InstallSection, &InstallEntry, PathBuf)> {
+    InstallSection::ALL
+      .iter()
+      .flat_map(|section| {
+        let dir = section.dir(prefix, name);
+        self
+          .section(*section)
+          .iter()
+          .map(move |entry| (*section, entry, entry.install_path(*section, &dir)))
+      })
+      .collect()
+  }
+}
+
+impl InstallEntry {
+  /// The path of the file once installed in `dir`, the directory of `section`.
+  fn install_path(&self, section: InstallSection, dir: &Path) -> PathBuf {
+    match (&self.dst, section) {
+      (Some(dst), InstallSection::Misc) => PathBuf::from(dst),
+      (Some(dst), _) => dir.join(dst),
+      (None, _) => {
+        let file_name = Path::new(&self.src)
+          .file_name()
+          .map(PathBuf::from)
+          .unwrap_or_default();
+        match (section, man_section(&file_name)) {
+          // `foo.1` goes to `man/man1/foo.1`
+          (InstallSection::Man, Some(man)) => dir.join(format!("man{}", man)).join(file_name),
+          _ => dir.join(file_name),
+        }
+      }
+    }
+  }
+}
+
+/// The section of a man page from its extension, like `3` for `foo.3o`.
+fn man_section(file_name: &Path) -> Option<char> {
+  let extension = file_name.extension()?.to_str()?;
+  extension.chars().next().filter(|c| c.is_ascii_digit())
+}
+
+/// A list of files, or a single file.
+fn entries(field: &str, value: &Value) -> Result<Vec<InstallEntry>, PackageError> {
+  let invalid_type = |v: &Value| invalid_type(field, "a list of files", v);
+  let entry = |v: &Value| {
+    let (src, dst) = match &v.kind {
+      ValueKind::Option(src, dst) => match (&src.kind, dst.as_slice()) {
+        (ValueKind::String(src), [dst]) => match &dst.kind {
+          ValueKind::String(dst) => (src, Some(dst.clone())),
+          _ => return Err(invalid_type(v)),
+        },
+        _ => return Err(invalid_type(v)),
+      },
+      ValueKind::String(src) => (src, None),
+      _ => return Err(invalid_type(v)),
+    };
+    Ok(InstallEntry {
+      src: src.strip_prefix('?').unwrap_or(src).to_string(),
+      optional: src.starts_with('?'),
+      dst,
+    })
+  };
+  match &value.kind {
+    ValueKind::List(lst) => lst.iter().map(entry).collect(),
+    _ => Ok(vec![entry(value)?]),
+  }
+}
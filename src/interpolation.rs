@@ -0,0 +1,242 @@
+//! Variable interpolation in strings.
+//!
+//! See more [Variables](https://opam.ocaml.org/doc/Manual.html#Variables)
+//!
+//! - `%{name}%`: a global variable
+//! - `%{pkg:name}%`: a variable of the package `pkg`, or of the current package if `pkg` is `_`
+//! - `%{pkg1+pkg2:name}%`: `true` if the boolean variable is true for all of the packages
+//! - `%{name?then:else}%`: `then` if the boolean variable is true, `else` if it is false
+//! - `%%`: a literal `%`
+//!
+//! ```rust
+//! use opam_file_rs::interpolation::*;
+//! let segments = parse("%{prefix}%/lib/%{_:name}%").unwrap();
+//! assert_eq!(segments.len(), 3);
+//! assert_eq!(segments[1], Segment::Literal("/lib/".to_string()));
+//! match &segments[2] {
+//!   Segment::Variable(var) => {
+//!     assert_eq!(var.packages, vec!["_".to_string()]);
+//!     assert_eq!(var.name, "name");
+//!     assert_eq!(var.pos, (15, 25));
+//!   }
+//!   _ => unreachable!(),
+//! }
+//! ```
+//!
+//! Interpolations are expanded with a [`Resolver`] as opam expands the strings of commands:
+//! an undefined variable is replaced with the empty string,
+//! and `%{name?then:else}%` with `else` if `name` is undefined or not a boolean.
+//!
+//! ```rust
+//! use opam_file_rs::filter::{Scope, VariableValue};
+//! let resolver = |scope: Scope, name: &str| match (scope, name) {
+//!   (Scope::Global, "prefix") => Some(VariableValue::String("/usr".to_string())),
+//!   (Scope::Current, "with-doc") => Some(VariableValue::Bool(false)),
+//!   _ => None,
+//! };
+//! let expand = |str| opam_file_rs::interpolation::expand(str, &resolver).unwrap();
+//! assert_eq!(expand("--prefix=%{prefix}%"), "--prefix=/usr");
+//! assert_eq!(expand("--%{_:with-doc?enable:disable}%-doc"), "--disable-doc");
+//! assert_eq!(expand("%{lib}%/stublibs"), "/stublibs");
+//! ```
+
+use std::fmt;
+use thiserror::Error;
+
+use super::filter::{self, FilterValue, Resolver};
+use super::value;
+
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub enum Segment {
+  /// Text outside of `%{ }%`, with `%%` already replaced with `%`.
+  Literal(String),
+  Variable(VariableRef),
+}
+
+/// The contents of a `%{ }%`.
+#[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct VariableRef {
+  /// Packages before the `:`, in order. Empty for a global variable, `["_"]` for the current package.
+  pub packages: Vec<String>,
+  pub name: String,
+  /// The strings after `?` and `:`, used when the variable is `true` and `false` respectively.
+  pub conditional: Option<(String, String)>,
+  /// Char positions of the whole `%{ ... }%` in the string.
+  pub pos: value::Pos,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Error)]
+pub enum InterpolationError {
+  /// `%{` without a matching `}%`.
+  #[error("unclosed interpolation at {}", .0.0)]
+  Unclosed(value::Pos),
+  /// `%{ }%` whose contents are not a variable.
+  #[error("invalid variable {0:?} at {}", .1.0)]
+  InvalidVariable(String, value::Pos),
+}
+
+/// Split `str` into literal text and interpolated variables.
+pub fn parse(str: &str) -> Result<Vec<Segment>, InterpolationError> {
+  let chars = str.chars().collect::<Vec<_>>();
+  let mut segments = Vec::new();
+  let mut literal = String::new();
+  let mut pos = 0;
+  while pos < chars.len() {
+    match (chars[pos], chars.get(pos + 1)) {
+      ('%', Some('%')) => {
+        literal.push('%');
+        pos += 2;
+      }
+      ('%', Some('{')) => {
+        let start = pos;
+        let body_start = pos + 2;
+        let body_end = (body_start..chars.len())
+          .find(|i| chars[*i] == '}' && chars.get(i + 1) == Some(&'%'))
+          .ok_or(InterpolationError::Unclosed((start, chars.len())))?;
+        let end = body_end + 2;
+        let body = chars[body_start..body_end].iter().collect::<String>();
+        let var = parse_variable(&body, (start, end))?;
+        if !literal.is_empty() {
+          segments.push(Segment::Literal(std::mem::take(&mut literal)));
+        }
+        segments.push(Segment::Variable(var));
+        pos = end;
+      }
+      (c, _) => {
+        literal.push(c);
+        pos += 1;
+      }
+    }
+  }
+  if !literal.is_empty() {
+    segments.push(Segment::Literal(literal));
+  }
+  Ok(segments)
+}
+
+/// Replace the interpolations in `str` with the values of the variables.
+pub fn expand(str: &str, resolver: &impl Resolver) -> Result<String, InterpolationError> {
+  let segments = parse(str)?;
+  Ok(
+    segments
+      .iter()
+      .map(|segment| match segment {
+        Segment::Literal(str) => str.clone(),
+        Segment::Variable(var) => expand_variable(var, resolver),
+      })
+      .collect(),
+  )
+}
+
+/// Expand every string in `value`, such as the value of `build:`.
+/// The filters of options are left as they are, to be [evaluated](crate::filter::eval) on their own.
+/// An error is returned with the position of the string containing the malformed interpolation.
+pub fn expand_value(
+  value: &value::Value,
+  resolver: &impl Resolver,
+) -> Result<value::Value, (InterpolationError, value::Pos)> {
+  use value::ValueKind;
+  let expand_box = |v: &value::Value| expand_value(v, resolver).map(Box::new);
+  let expand_all = |lst: &[value::Value]| {
+    lst
+      .iter()
+      .map(|v| expand_value(v, resolver))
+      .collect::<Result<Vec<_>, _>>()
+  };
+  let kind = match &value.kind {
+    ValueKind::String(str) => {
+      ValueKind::String(expand(str, resolver).map_err(|err| (err, value.pos))?)
+    }
+    ValueKind::RelOp(op, l, r) => ValueKind::RelOp(op.clone(), expand_box(l)?, expand_box(r)?),
+    ValueKind::PrefixRelOp(op, r) => ValueKind::PrefixRelOp(op.clone(), expand_box(r)?),
+    ValueKind::LogOp(op, l, r) => ValueKind::LogOp(op.clone(), expand_box(l)?, expand_box(r)?),
+    ValueKind::PfxOp(op, r) => ValueKind::PfxOp(op.clone(), expand_box(r)?),
+    ValueKind::List(lst) => ValueKind::List(expand_all(lst)?),
+    ValueKind::Group(lst) => ValueKind::Group(expand_all(lst)?),
+    ValueKind::Option(v, lst) => ValueKind::Option(expand_box(v)?, lst.clone()),
+    ValueKind::EnvBinding(l, op, r) => {
+      ValueKind::EnvBinding(expand_box(l)?, op.clone(), expand_box(r)?)
+    }
+    kind => kind.clone(),
+  };
+  Ok(value::Value {
+    kind,
+    pos: value.pos,
+  })
+}
+
+fn expand_variable(var: &VariableRef, resolver: &impl Resolver) -> String {
+  // as opam does, a variable of several packages that is not a boolean is undefined
+  let v = filter::resolve_variable(var, resolver).unwrap_or(FilterValue::Undefined);
+  match (&var.conditional, v) {
+    (Some((then_str, else_str)), v) => {
+      let b = match v {
+        FilterValue::Bool(b) => b,
+        FilterValue::String(str) => str == "true",
+        FilterValue::Undefined => false,
+      };
+      if b { then_str } else { else_str }.clone()
+    }
+    (None, FilterValue::Bool(b)) => b.to_string(),
+    (None, FilterValue::String(str)) => str,
+    (None, FilterValue::Undefined) => String::new(),
+  }
+}
+
+/// `[<pkg> ("+" <pkg>)* ":"] <name> ["?" <then> ":" <else>]`
+pub(crate) fn parse_variable(
+  body: &str,
+  pos: value::Pos,
+) -> Result<VariableRef, InterpolationError> {
+  let invalid = || InterpolationError::InvalidVariable(body.to_string(), pos);
+  let (ident, conditional) = match body.split_once('?') {
+    Some((ident, conditional)) => {
+      let (then_str, else_str) = conditional.split_once(':').ok_or_else(invalid)?;
+      (ident, Some((then_str.to_string(), else_str.to_string())))
+    }
+    None => (body, None),
+  };
+  let (packages, name) = match ident.rsplit_once(':') {
+    Some((packages, name)) => (packages.split('+').map(|s| s.to_string()).collect(), name),
+    None => (Vec::new(), ident),
+  };
+  let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+  let is_package_char = |c: char| is_name_char(c) || c == '+' || c == '.';
+  let valid_name = !name.is_empty() && name.chars().all(is_name_char);
+  let valid_packages = packages
+    .iter()
+    .all(|pkg: &String| !pkg.is_empty() && pkg.chars().all(is_package_char));
+  if valid_name && valid_packages {
+    Ok(VariableRef {
+      packages,
+      name: name.to_string(),
+      conditional,
+      pos,
+    })
+  } else {
+    Err(invalid())
+  }
+}
+
+impl fmt::Display for VariableRef {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "%{{")?;
+    if !self.packages.is_empty() {
+      write!(f, "{}:", self.packages.join("+"))?;
+    }
+    write!(f, "{}", self.name)?;
+    if let Some((then_str, else_str)) = &self.conditional {
+      write!(f, "?{}:{}", then_str, else_str)?;
+    }
+    write!(f, "}}%")
+  }
+}
+
+impl fmt::Display for Segment {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Segment::Literal(str) => write!(f, "{}", str.replace('%', "%%")),
+      Segment::Variable(var) => write!(f, "{}", var),
+    }
+  }
+}
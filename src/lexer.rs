@@ -1,5 +1,6 @@
 use thiserror::Error;
 
+use super::cst;
 use super::value;
 
 #[allow(unused)]
@@ -7,6 +8,7 @@ pub type Token = (TokenKind, usize, usize);
 
 #[derive(Debug, Clone, PartialEq, Eq, Ord, PartialOrd, Hash)]
 #[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
 #[allow(unused)]
 pub enum TokenKind {
   EOF,
@@ -77,6 +79,31 @@ pub fn get_value_env(kind: TokenKind) -> Option<value::EnvUpdateOpKind> {
   }
 }
 
+/// Human readable name of a grammar terminal, as reported in `expected` lists of parse errors.
+pub fn describe_terminal(name: &str) -> String {
+  let name = match name {
+    "Tok_EOF" => "end of input",
+    "Tok_STRING" => "string",
+    "Tok_IDENT" => "identifier",
+    "Tok_BOOL" => "bool",
+    "Tok_INT" => "int",
+    "Tok_LBRACKET" => "`[`",
+    "Tok_RBRACKET" => "`]`",
+    "Tok_LPAR" => "`(`",
+    "Tok_RPAR" => "`)`",
+    "Tok_LBRACE" => "`{`",
+    "Tok_RBRACE" => "`}`",
+    "Tok_COLON" => "`:`",
+    "Tok_AND" => "`&`",
+    "Tok_OR" => "`|`",
+    "Tok_RELOP" => "relational operator",
+    "Tok_PFXOP" => "`!` or `?`",
+    "Tok_ENVOP" => "environment update operator",
+    name => name,
+  };
+  name.to_string()
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Error)]
 pub enum LexErrorKind {
   #[error("invalid char: {0}")]
@@ -145,7 +172,7 @@ fn lex_token(input: &[char], pos: usize) -> Result<(Vec<Token>, usize), LexError
       }
       '(' => match input.get(pos + 1) {
         Some('*') => {
-          let new_pos = lex_comment(1, input, pos + 1);
+          let new_pos = lex_comment(input, pos + 2);
           pos = new_pos;
         }
         _ => {
@@ -252,7 +279,7 @@ fn lex_token(input: &[char], pos: usize) -> Result<(Vec<Token>, usize), LexError
       },
       '"' => match (input.get(pos + 1), input.get(pos + 2)) {
         (Some('"'), Some('"')) => {
-          let (token, new_pos) = lex_string_triple(input, pos + 3)?;
+          let (token, new_pos) = lex_string_triple(input, pos)?;
           tokens.push(token);
           pos = new_pos;
         }
@@ -278,7 +305,7 @@ fn lex_token(input: &[char], pos: usize) -> Result<(Vec<Token>, usize), LexError
         tokens.push(token);
         pos = new_pos;
       }
-      c if c.is_ascii_alphabetic() => {
+      c if c.is_ascii_alphabetic() || c == '_' => {
         let (token, new_pos) = lex_ident(input, pos);
         tokens.push(token);
         pos = new_pos;
@@ -289,13 +316,13 @@ fn lex_token(input: &[char], pos: usize) -> Result<(Vec<Token>, usize), LexError
   Ok((tokens, pos))
 }
 
-fn lex_comment(depth: usize, input: &[char], pos: usize) -> usize {
+fn lex_comment(input: &[char], pos: usize) -> usize {
   let mut pos = pos;
   while pos < input.len() {
     match input[pos] {
       '*' => match input.get(pos + 1) {
         Some(')') => {
-          pos += 1;
+          pos += 2;
           break;
         }
         _ => {
@@ -304,8 +331,7 @@ fn lex_comment(depth: usize, input: &[char], pos: usize) -> usize {
       },
       '(' => match input.get(pos + 1) {
         Some('*') => {
-          pos += 1;
-          let new_pos = lex_comment(depth + 1, input, pos);
+          let new_pos = lex_comment(input, pos + 2);
           pos = new_pos;
         }
         _ => {
@@ -318,9 +344,39 @@ fn lex_comment(depth: usize, input: &[char], pos: usize) -> usize {
   pos
 }
 
+/// Split `input[start..end]`, the text between two tokens, into whitespace and comments.
+pub fn lex_trivia(
+  input: &[char],
+  start: usize,
+  end: usize,
+) -> Vec<(cst::TriviaKind, usize, usize)> {
+  let mut pos = start;
+  let mut trivia = Vec::new();
+  while pos < end {
+    let (kind, new_pos) = match (input[pos], input.get(pos + 1)) {
+      ('#', _) => (
+        cst::TriviaKind::LineComment,
+        lex_line_comment(input, pos + 1),
+      ),
+      ('(', Some('*')) => (cst::TriviaKind::BlockComment, lex_comment(input, pos + 2)),
+      _ => {
+        let mut new_pos = pos + 1;
+        while new_pos < end && !matches!(input[new_pos], '#' | '(') {
+          new_pos += 1;
+        }
+        (cst::TriviaKind::Whitespace, new_pos)
+      }
+    };
+    let new_pos = new_pos.min(end);
+    trivia.push((kind, pos, new_pos));
+    pos = new_pos;
+  }
+  trivia
+}
+
 fn lex_line_comment(input: &[char], pos: usize) -> usize {
   let mut pos = pos;
-  while pos < input.len() && !('\n' == input[pos]) {
+  while pos < input.len() && input[pos] != '\n' {
     pos += 1;
   }
   pos
@@ -335,7 +391,7 @@ fn lex_string(input: &[char], pos: usize) -> Result<(Token, usize), LexError> {
       None => return Err(error_eof(pos)),
       Some(c) => match c {
         '\\' => {
-          let (escape_str, new_pos) = lex_escape(input, pos)?;
+          let (escape_str, new_pos) = lex_escape(input, s_pos)?;
           s_pos = new_pos;
           str.push_str(&escape_str)
         }
@@ -344,7 +400,7 @@ fn lex_string(input: &[char], pos: usize) -> Result<(Token, usize), LexError> {
           break;
         }
         _ => {
-          s_pos = s_pos + 1;
+          s_pos += 1;
           str.push(*c)
         }
       },
@@ -356,13 +412,13 @@ fn lex_string(input: &[char], pos: usize) -> Result<(Token, usize), LexError> {
 fn lex_string_triple(input: &[char], pos: usize) -> Result<(Token, usize), LexError> {
   let mut str = String::new();
   let start = pos;
-  let mut s_pos = start;
+  let mut s_pos = start + 3;
   loop {
     match input.get(s_pos) {
       None => return Err(error_eof(pos)),
       Some(c) => match c {
         '\\' => {
-          let (escape_str, new_pos) = lex_escape(input, pos)?;
+          let (escape_str, new_pos) = lex_escape(input, s_pos)?;
           s_pos = new_pos;
           str.push_str(&escape_str)
         }
@@ -377,12 +433,12 @@ fn lex_string_triple(input: &[char], pos: usize) -> Result<(Token, usize), LexEr
           }
           _ => {
             s_pos += 1;
-            str.push_str("\"");
+            str.push('"');
           }
         },
         _ => {
           str.push(*c);
-          s_pos = s_pos + 1;
+          s_pos += 1;
         }
       },
     }
@@ -401,7 +457,7 @@ fn lex_escape(input: &[char], pos: usize) -> Result<(String, usize), LexError> {
     Some('b') => Ok(("\u{0008}".to_string(), pos + 2)),
     Some('x') => match (input.get(pos + 2), input.get(pos + 3)) {
       (Some(c1), Some(c2)) if (c1.is_ascii_hexdigit() && c2.is_ascii_hexdigit()) => {
-        let hex = vec![*c1, *c2].iter().collect::<String>();
+        let hex = [*c1, *c2].iter().collect::<String>();
         let hex_i64 = i64::from_str_radix(&hex, 16).unwrap();
         let str = String::from_utf8(vec![hex_i64 as u8]).unwrap();
         Ok((str, pos + 4))
@@ -410,8 +466,8 @@ fn lex_escape(input: &[char], pos: usize) -> Result<(String, usize), LexError> {
     },
     Some(c) if c.is_ascii_digit() => match (input.get(pos + 2), input.get(pos + 3)) {
       (Some(c1), Some(c2)) if (c1.is_ascii_digit() && c2.is_ascii_digit()) => {
-        let hex = vec![*c, *c1, *c2].iter().collect::<String>();
-        let hex_i64 = i64::from_str_radix(&hex, 10).unwrap();
+        let hex = [*c, *c1, *c2].iter().collect::<String>();
+        let hex_i64 = hex.parse::<i64>().unwrap();
         let str = String::from_utf8(vec![hex_i64 as u8]).unwrap();
         Ok((str, pos + 4))
       }
@@ -446,13 +502,20 @@ fn check_lex_escape_digit_unicode() {
   )
 }
 
+#[test]
+fn check_lex_string_escape() {
+  assert_eq!(
+    lex_string(&r#""a\"b\\c""#.chars().collect::<Vec<_>>(), 0),
+    Ok(((TokenKind::STRING("a\"b\\c".to_string()), 0, 9), 9))
+  )
+}
+
 fn lex_int(is_minus: bool, input: &[char], pos: usize) -> (Token, usize) {
   let start = pos;
-  let mut pos = pos;
-  let mut str = if is_minus {
-    String::new()
+  let (mut pos, mut str) = if is_minus {
+    (pos + 1, "-".to_string())
   } else {
-    "-".to_string()
+    (pos, String::new())
   };
   while pos < input.len() {
     if input[pos].is_ascii_digit() {
@@ -466,24 +529,64 @@ fn lex_int(is_minus: bool, input: &[char], pos: usize) -> (Token, usize) {
   ((TokenKind::INT(int), start, pos), pos)
 }
 
+#[test]
+fn check_lex_int() {
+  assert_eq!(
+    lex(" 42 -7"),
+    Ok(vec![
+      (TokenKind::INT(42), 1, 3),
+      (TokenKind::INT(-7), 4, 6),
+      (TokenKind::EOF, 6, 7)
+    ])
+  )
+}
+
+/// `ident ::= (<id> | "_") ("+" (<id> | "_"))* (":" <id>)?`
+///
+/// e.g. `name`, `ocaml:version`, `_:doc`, `lwt+ssl:installed`
 fn lex_ident(input: &[char], pos: usize) -> (Token, usize) {
   let start = pos;
-  let mut pos = pos;
-  let mut str = String::new();
-  while pos < input.len() {
-    let c = input[pos];
-    if c.is_ascii_alphabetic() || c.is_ascii_digit() || c == '_' || c == '-' {
-      str.push(c);
-      pos += 1;
-    } else {
-      break;
-    }
+  let mut pos = scan_ident_part(input, pos).unwrap_or(pos + 1);
+  while let (Some('+'), Some(new_pos)) = (input.get(pos), scan_ident_part(input, pos + 1)) {
+    pos = new_pos;
+  }
+  if let (Some(':'), Some(new_pos)) = (input.get(pos), scan_id(input, pos + 1)) {
+    pos = new_pos;
   }
-  if str == "true".to_string() {
+  let str = input[start..pos].iter().collect::<String>();
+  if str == "true" {
     ((TokenKind::BOOL(true), start, pos), pos)
-  } else if str == "false".to_string() {
+  } else if str == "false" {
     ((TokenKind::BOOL(false), start, pos), pos)
   } else {
     ((TokenKind::IDENT(str), start, pos), pos)
   }
 }
+
+/// `<id> | "_"`
+fn scan_ident_part(input: &[char], pos: usize) -> Option<usize> {
+  match scan_id(input, pos) {
+    Some(pos) => Some(pos),
+    None if input.get(pos) == Some(&'_') => Some(pos + 1),
+    None => None,
+  }
+}
+
+/// `<id>`: a sequence of letters, digits, `_` and `-` that contains at least one letter.
+fn scan_id(input: &[char], pos: usize) -> Option<usize> {
+  let mut pos = pos;
+  let mut has_letter = false;
+  while let Some(c) = input.get(pos) {
+    if c.is_ascii_alphabetic() {
+      has_letter = true;
+    } else if !(c.is_ascii_digit() || *c == '_' || *c == '-') {
+      break;
+    }
+    pos += 1;
+  }
+  if has_letter {
+    Some(pos)
+  } else {
+    None
+  }
+}
@@ -56,7 +56,7 @@ fn main() {
     ]
   "#;
   let opam = opam_file_rs::parse(opam_str).unwrap();
-  println!("{}", opam_file_rs::value::format_opam_file(opam));
+  println!("{}", opam_file_rs::value::format_opam_file(&opam));
 }
 ```
 ---
@@ -66,14 +66,34 @@ fn main() {
 #[macro_use]
 extern crate lalrpop_util;
 
+use std::fmt;
 use thiserror::Error;
 
+pub mod config;
+pub mod cst;
+pub mod edit;
+mod fields;
+pub mod filter;
+pub mod formula;
+pub mod install;
+pub mod interpolation;
 mod lexer;
+pub mod lint;
+pub mod package;
+pub mod pretty;
+pub mod repository;
+pub mod root;
+pub mod source_map;
+pub mod switch;
 pub mod value;
+pub mod version;
 
 mod tests;
 
-lalrpop_mod!(parser);
+lalrpop_mod!(
+  #[allow(clippy::all)]
+  parser
+);
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Error)]
 pub enum OpamFileError {
@@ -81,8 +101,97 @@ pub enum OpamFileError {
   LexInvalidChar(char, usize, usize),
   #[error("EOF")]
   LexEof,
-  #[error("parse error")]
-  Parse,
+  #[error("{0}")]
+  Parse(ParseError),
+}
+
+/// A syntax error reported by the parser.
+///
+/// `Display` renders the message followed by the offending source line with the token underlined:
+///
+/// ```text
+/// unexpected `}` at line 2, column 10, expected one of string, identifier, ...
+///   |
+/// 2 |     name: }
+///   |           ^
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ParseError {
+  /// Source text of the offending token, or `None` if the input ended unexpectedly.
+  pub token: Option<String>,
+  /// Position of the offending token.
+  pub pos: value::Pos,
+  /// Human readable names of the tokens the parser would have accepted instead.
+  pub expected: Vec<String>,
+  /// 1-origin line of `pos.0`.
+  pub line: usize,
+  /// 1-origin column of `pos.0`, counted in chars.
+  pub column: usize,
+  /// The source line containing `pos.0`, without its line terminator.
+  pub source_line: String,
+}
+
+impl ParseError {
+  fn new(
+    input: &[char],
+    source_map: &source_map::SourceMap,
+    token: Option<String>,
+    pos: value::Pos,
+    expected: Vec<String>,
+  ) -> Self {
+    let start = pos.0.min(input.len());
+    let line_col = source_map.line_col(start).unwrap();
+    let line_start = start - line_col.column;
+    let line_end = input[start..]
+      .iter()
+      .position(|c| *c == '\n')
+      .map(|i| start + i)
+      .unwrap_or(input.len());
+    let source_line = input[line_start..line_end]
+      .iter()
+      .collect::<String>()
+      .trim_end_matches('\r')
+      .to_string();
+    ParseError {
+      token,
+      pos,
+      expected,
+      line: line_col.line + 1,
+      column: line_col.column + 1,
+      source_line,
+    }
+  }
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match &self.token {
+      Some(token) => write!(f, "unexpected `{}`", token)?,
+      None => write!(f, "unexpected end of input")?,
+    }
+    write!(f, " at line {}, column {}", self.line, self.column)?;
+    match self.expected.as_slice() {
+      [] => {}
+      [expected] => write!(f, ", expected {}", expected)?,
+      expected => write!(f, ", expected one of {}", expected.join(", "))?,
+    }
+    let line_no = self.line.to_string();
+    let gutter = " ".repeat(line_no.len());
+    let line_len = self.source_line.chars().count();
+    let width = (self.pos.1.saturating_sub(self.pos.0))
+      .min(line_len.saturating_sub(self.column - 1))
+      .max(1);
+    writeln!(f)?;
+    writeln!(f, "{} |", gutter)?;
+    writeln!(f, "{} | {}", line_no, self.source_line)?;
+    write!(
+      f,
+      "{} | {}{}",
+      gutter,
+      " ".repeat(self.column - 1),
+      "^".repeat(width)
+    )
+  }
 }
 
 /// See more [Common file format](https://opam.ocaml.org/doc/Manual.html#Common-file-format)
@@ -97,6 +206,66 @@ pub fn parse(input: &str) -> Result<value::OpamFile, OpamFileError> {
   };
   match parser::mainParser::new().parse(lex) {
     Ok(file) => Ok(file),
-    Err(_) => Err(OpamFileError::Parse),
+    Err(err) => Err(OpamFileError::Parse(make_parse_error(input, err))),
+  }
+}
+
+/// Same as [`parse`], also returning a [`source_map::SourceMap`] to convert the positions in the result.
+pub fn parse_with_source_map(
+  input: &str,
+) -> Result<(value::OpamFile, source_map::SourceMap), OpamFileError> {
+  let file = parse(input)?;
+  Ok((file, source_map::SourceMap::new(input)))
+}
+
+fn make_parse_error(
+  input: &str,
+  err: lalrpop_util::ParseError<(), lexer::Token, &'static str>,
+) -> ParseError {
+  use lalrpop_util::ParseError as E;
+  let source_map = source_map::SourceMap::new(input);
+  let input = input.chars().collect::<Vec<_>>();
+  let describe = |expected: Vec<String>| -> Vec<String> {
+    expected
+      .iter()
+      .map(|t| lexer::describe_terminal(t))
+      .collect()
+  };
+  let token_text = |start: usize, end: usize, kind: &lexer::TokenKind| match kind {
+    lexer::TokenKind::EOF => None,
+    _ => Some(
+      input[start.min(input.len())..end.min(input.len())]
+        .iter()
+        .collect::<String>(),
+    ),
+  };
+  match err {
+    E::UnrecognizedToken {
+      token: (_, (kind, start, end), _),
+      expected,
+    } => ParseError::new(
+      &input,
+      &source_map,
+      token_text(start, end, &kind),
+      (start, end),
+      describe(expected),
+    ),
+    E::ExtraToken {
+      token: (_, (kind, start, end), _),
+    } => ParseError::new(
+      &input,
+      &source_map,
+      token_text(start, end, &kind),
+      (start, end),
+      Vec::new(),
+    ),
+    E::UnrecognizedEOF { expected, .. } => {
+      let end = input.len();
+      ParseError::new(&input, &source_map, None, (end, end), describe(expected))
+    }
+    E::InvalidToken { .. } | E::User { .. } => {
+      let end = input.len();
+      ParseError::new(&input, &source_map, None, (end, end), Vec::new())
+    }
   }
 }
@@ -0,0 +1,580 @@
+//! Checks of opam files, as done by `opam lint`.
+//!
+//! See more [opam lint](https://opam.ocaml.org/doc/man/opam-lint.html)
+//!
+//! Each [`Diagnostic`] has the number and severity `opam lint` uses for the same rule,
+//! and the position of the offending item or value, if there is one.
+//!
+//! ```rust
+//! use opam_file_rs::lint::{lint, Severity};
+//! let opam = opam_file_rs::parse(r#"
+//!   opam-version: "2.0"
+//!   synopsis: "a package."
+//!   maintainer: "foo@example.com"
+//!   authors: "Foo"
+//!   license: "MIT"
+//!   homepage: "https://example.com/foo"
+//!   bug-reports: "https://example.com/foo/issues"
+//!   dev-repo: "https://example.com/foo"
+//! "#).unwrap();
+//! let diagnostics = lint(&opam);
+//! assert_eq!(
+//!   diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>(),
+//!   [
+//!     "error 42: The 'dev-repo:' field doesn't use version control",
+//!     "warning 47: Synopsis should start with a capital and not end with a dot",
+//!   ]
+//! );
+//! assert_eq!(diagnostics[0].severity, Severity::Error);
+//! assert_eq!(diagnostics[0].pos, Some((212, 237)));
+//! ```
+
+use std::fmt;
+
+use super::interpolation;
+use super::package::OpamPackage;
+use super::value::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub enum Severity {
+  Warning,
+  Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Diagnostic {
+  /// The number of the rule in `opam lint`.
+  pub number: usize,
+  pub severity: Severity,
+  pub message: String,
+  /// The position of the offending item or value, or `None` for a missing field.
+  pub pos: Option<Pos>,
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let severity = match self.severity {
+      Severity::Warning => "warning",
+      Severity::Error => "error",
+    };
+    write!(f, "{} {}: {}", severity, self.number, self.message)
+  }
+}
+
+/// Check `file`, returning the diagnostics sorted by number.
+pub fn lint(file: &OpamFile) -> Vec<Diagnostic> {
+  let mut diagnostics = Vec::new();
+  let mut push = |number, severity, message: String, pos| {
+    diagnostics.push(Diagnostic {
+      number,
+      severity,
+      message,
+      pos,
+    })
+  };
+  let fields = file
+    .file_contents
+    .iter()
+    .filter_map(|item| match item {
+      OpamFileItem::Variable(pos, name, value) => Some((*pos, name.as_str(), value)),
+      OpamFileItem::Section(_, _) => None,
+    })
+    .collect::<Vec<_>>();
+  let field = |name: &str| fields.iter().find(|(_, n, _)| *n == name).map(|f| f.2);
+  let string_field = |name: &str| match field(name).map(|v| &v.kind) {
+    Some(ValueKind::String(str)) => Some(str.as_str()),
+    _ => None,
+  };
+
+  // the type of each field, and duplicate fields
+  let mut package = OpamPackage::default();
+  for (i, (pos, name, value)) in fields.iter().enumerate() {
+    if fields[..i].iter().any(|(_, n, _)| n == name) {
+      push(
+        3,
+        Severity::Error,
+        format!("File format error: duplicate field '{}'", name),
+        Some(*pos),
+      );
+    } else if let Err(err) = package.set_field(name, value) {
+      push(
+        3,
+        Severity::Error,
+        format!("File format error in '{}': {}", name, err),
+        Some(value.pos),
+      );
+    }
+  }
+
+  match field("opam-version") {
+    None => push(
+      1,
+      Severity::Error,
+      "Missing field 'opam-version'".to_string(),
+      None,
+    ),
+    Some(value) => {
+      if let ValueKind::String(version) = &value.kind {
+        if version.split('.').count() > 2 {
+          push(
+            20,
+            Severity::Warning,
+            "Field 'opam-version' refers to the patch version of opam, it should be of the form MAJOR.MINOR".to_string(),
+            Some(value.pos),
+          );
+        }
+        if crate::version::compare(version, OPAM_VERSION) == std::cmp::Ordering::Greater {
+          push(
+            21,
+            Severity::Error,
+            "Field 'opam-version' doesn't match the current version, validation may not be accurate".to_string(),
+            Some(value.pos),
+          );
+        }
+      }
+    }
+  }
+
+  let empty = fields
+    .iter()
+    .filter(|(_, name, value)| {
+      NON_EMPTY_FIELDS.contains(name)
+        && match &value.kind {
+          ValueKind::String(str) => str.is_empty(),
+          ValueKind::List(lst) => lst.is_empty(),
+          _ => false,
+        }
+    })
+    .collect::<Vec<_>>();
+  if let Some((_, _, value)) = empty.first() {
+    push(
+      22,
+      Severity::Error,
+      format!(
+        "Some fields are present but empty; remove or fill them: {}",
+        empty.iter().map(|f| f.1).collect::<Vec<_>>().join(", ")
+      ),
+      Some(value.pos),
+    );
+  }
+
+  let missing = [
+    (23, Severity::Error, "maintainer"),
+    (25, Severity::Warning, "authors"),
+    (35, Severity::Warning, "homepage"),
+    (36, Severity::Warning, "bug-reports"),
+    (37, Severity::Warning, "dev-repo"),
+    (68, Severity::Warning, "license"),
+  ];
+  for (number, severity, name) in missing.iter() {
+    if field(name).is_none() {
+      push(
+        *number,
+        *severity,
+        format!("Missing field '{}'", name),
+        None,
+      );
+    }
+  }
+
+  for (pos, name, _) in &fields {
+    let deprecated = match *name {
+      "ocaml-version" => Some((32, Severity::Error, "Field 'ocaml-version:' is deprecated, use a dependency towards the 'ocaml' package instead")),
+      "os" => Some((33, Severity::Error, "Field 'os' is deprecated, use 'available' and the 'os' variable instead")),
+      "build-test" | "build-doc" => Some((48, Severity::Warning, "The fields 'build-test:' and 'build-doc:' are deprecated, and should be replaced by uses of the 'with-test' and 'with-doc' filter variables in the 'build:' and 'install:' fields, and by the newer 'run-test:' field")),
+      _ => None,
+    };
+    if let Some((number, severity, message)) = deprecated {
+      push(number, severity, message.to_string(), Some(*pos));
+    }
+  }
+
+  // variables of the commands
+  let dependencies = ["depends", "depopts"]
+    .iter()
+    .filter_map(|name| field(name))
+    .filter_map(|value| crate::formula::PackageFormula::from_value(value).ok())
+    .flat_map(|formula| {
+      formula
+        .package_names()
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>()
+    })
+    .collect::<Vec<_>>();
+  let self_name = string_field("name");
+  let mut variables = Vec::new();
+  for name in COMMAND_FIELDS.iter() {
+    if let Some(value) = field(name) {
+      collect_variables(value, &mut variables);
+    }
+  }
+  let undefined = variables
+    .iter()
+    .filter(|var| var.packages.is_empty() && !is_global_variable(&var.name))
+    .collect::<Vec<_>>();
+  if let Some(var) = undefined.first() {
+    push(
+      58,
+      Severity::Warning,
+      format!(
+        "Undefined variables in commands: {}",
+        undefined
+          .iter()
+          .map(|var| var.name.as_str())
+          .collect::<Vec<_>>()
+          .join(", ")
+      ),
+      Some(var.pos),
+    );
+  }
+  let mut unknown_packages = variables
+    .iter()
+    .flat_map(|var| var.packages.iter().map(move |package| (package, var.pos)))
+    .filter(|(package, _)| {
+      package.as_str() != "_"
+        && Some(package.as_str()) != self_name
+        && !dependencies.contains(package)
+    })
+    .collect::<Vec<_>>();
+  unknown_packages.sort();
+  unknown_packages.dedup_by(|(a, _), (b, _)| a == b);
+  if let Some((_, pos)) = unknown_packages.first() {
+    push(
+      41,
+      Severity::Warning,
+      format!(
+        "Some packages are mentioned in package scripts or features, but there is no dependency or depopt toward them: {}",
+        unknown_packages
+          .iter()
+          .map(|(package, _)| package.as_str())
+          .collect::<Vec<_>>()
+          .join(", ")
+      ),
+      Some(*pos),
+    );
+  }
+
+  if let Some(value) = field("dev-repo") {
+    if let ValueKind::String(url) = &value.kind {
+      if !is_vcs_url(url) {
+        push(
+          42,
+          Severity::Error,
+          "The 'dev-repo:' field doesn't use version control".to_string(),
+          Some(value.pos),
+        );
+      }
+    }
+  }
+
+  if let Some(value) = field("synopsis") {
+    if let ValueKind::String(synopsis) = &value.kind {
+      // `is_none_or` needs Rust 1.82
+      #[allow(clippy::unnecessary_map_or)]
+      let capital = synopsis.chars().next().map_or(true, |c| !c.is_lowercase());
+      if !capital || synopsis.ends_with('.') {
+        push(
+          47,
+          Severity::Warning,
+          "Synopsis should start with a capital and not end with a dot".to_string(),
+          Some(value.pos),
+        );
+      }
+    }
+  }
+  #[allow(clippy::unnecessary_map_or)]
+  let is_empty = |name| string_field(name).map_or(true, |str: &str| str.trim().is_empty());
+  if is_empty("synopsis") && is_empty("description") {
+    push(
+      57,
+      Severity::Error,
+      "Synopsis and description must not be both empty".to_string(),
+      field("synopsis").map(|v| v.pos),
+    );
+  }
+
+  if let Some(value) = field("license") {
+    let licenses = match &value.kind {
+      ValueKind::String(_) => vec![value],
+      ValueKind::List(lst) => lst.iter().collect(),
+      _ => vec![],
+    };
+    for license in licenses {
+      if let ValueKind::String(str) = &license.kind {
+        if !is_spdx_expression(str) {
+          push(
+            62,
+            Severity::Warning,
+            format!(
+              "License doesn't adhere to the SPDX standard, see https://spdx.org/licenses/: \"{}\"",
+              str
+            ),
+            Some(license.pos),
+          );
+        }
+      }
+    }
+  }
+
+  diagnostics.sort_by_key(|d| d.number);
+  diagnostics
+}
+
+/// The latest version of the file format the checks follow. Files of later versions get error 21.
+const OPAM_VERSION: &str = "2.2";
+
+/// Fields that must not be an empty string or list.
+const NON_EMPTY_FIELDS: [&str; 11] = [
+  "maintainer",
+  "authors",
+  "license",
+  "homepage",
+  "doc",
+  "bug-reports",
+  "dev-repo",
+  "tags",
+  "synopsis",
+  "description",
+  "flags",
+];
+
+const COMMAND_FIELDS: [&str; 4] = ["build", "install", "remove", "run-test"];
+
+/// Variables defined by opam without a package, and variables of the package itself.
+const GLOBAL_VARIABLES: [&str; 47] = [
+  "opam-version",
+  "root",
+  "jobs",
+  "make",
+  "arch",
+  "os",
+  "os-distribution",
+  "os-family",
+  "os-version",
+  "switch",
+  "prefix",
+  "lib",
+  "libexec",
+  "bin",
+  "sbin",
+  "share",
+  "doc",
+  "etc",
+  "man",
+  "toplevel",
+  "stublibs",
+  "user",
+  "group",
+  "sys-ocaml-version",
+  "sys-ocaml-arch",
+  "sys-ocaml-cc",
+  "sys-ocaml-libc",
+  "ocaml-native",
+  "ocaml-native-tools",
+  "ocaml-native-dynlink",
+  "name",
+  "version",
+  "depends",
+  "installed",
+  "pinned",
+  "dev",
+  "build",
+  "build-id",
+  "hash",
+  "opamfile",
+  "with-test",
+  "with-doc",
+  "with-dev-setup",
+  "post",
+  "enable",
+  "sys-ocaml-system",
+  "opamfile-loc",
+];
+
+fn is_global_variable(name: &str) -> bool {
+  GLOBAL_VARIABLES.contains(&name)
+}
+
+/// Push the variables used as arguments, in filters and in `%{ }%` in `value`.
+fn collect_variables(value: &Value, variables: &mut Vec<interpolation::VariableRef>) {
+  match &value.kind {
+    ValueKind::Ident(name) => variables.extend(interpolation::parse_variable(name, value.pos)),
+    ValueKind::String(str) => {
+      if let Ok(segments) = interpolation::parse(str) {
+        variables.extend(segments.into_iter().filter_map(|segment| match segment {
+          interpolation::Segment::Variable(mut var) => {
+            var.pos = value.pos;
+            Some(var)
+          }
+          interpolation::Segment::Literal(_) => None,
+        }))
+      }
+    }
+    ValueKind::RelOp(_, l, r) | ValueKind::LogOp(_, l, r) | ValueKind::EnvBinding(l, _, r) => {
+      collect_variables(l, variables);
+      collect_variables(r, variables);
+    }
+    ValueKind::PrefixRelOp(_, v) | ValueKind::PfxOp(_, v) => collect_variables(v, variables),
+    ValueKind::List(lst) | ValueKind::Group(lst) => {
+      lst.iter().for_each(|v| collect_variables(v, variables))
+    }
+    ValueKind::Option(v, lst) => {
+      collect_variables(v, variables);
+      lst.iter().for_each(|v| collect_variables(v, variables));
+    }
+    ValueKind::Bool(_) | ValueKind::Int(_) => (),
+  }
+}
+
+/// Whether opam would fetch `url` with a version control system.
+fn is_vcs_url(url: &str) -> bool {
+  let vcs_prefixes = ["git://", "git+", "hg://", "hg+", "darcs://", "darcs+"];
+  vcs_prefixes.iter().any(|prefix| url.starts_with(prefix))
+    || url.trim_end_matches('/').ends_with(".git")
+}
+
+/// Common license identifiers of the SPDX license list.
+const SPDX_LICENSES: [&str; 68] = [
+  "0BSD",
+  "AFL-3.0",
+  "AGPL-3.0-only",
+  "AGPL-3.0-or-later",
+  "Apache-1.1",
+  "Apache-2.0",
+  "Artistic-2.0",
+  "BSD-1-Clause",
+  "BSD-2-Clause",
+  "BSD-2-Clause-Patent",
+  "BSD-3-Clause",
+  "BSD-3-Clause-Clear",
+  "BSD-4-Clause",
+  "BSL-1.0",
+  "CC-BY-4.0",
+  "CC-BY-SA-3.0",
+  "CC-BY-SA-4.0",
+  "CC0-1.0",
+  "CDDL-1.0",
+  "CECILL-2.0",
+  "CECILL-2.1",
+  "CECILL-B",
+  "CECILL-C",
+  "EPL-1.0",
+  "EPL-2.0",
+  "EUPL-1.2",
+  "GPL-1.0-only",
+  "GPL-1.0-or-later",
+  "GPL-2.0-only",
+  "GPL-2.0-or-later",
+  "GPL-3.0-only",
+  "GPL-3.0-or-later",
+  "ISC",
+  "LGPL-2.0-only",
+  "LGPL-2.0-or-later",
+  "LGPL-2.1-only",
+  "LGPL-2.1-or-later",
+  "LGPL-3.0-only",
+  "LGPL-3.0-or-later",
+  "LPPL-1.3c",
+  "MIT",
+  "MIT-0",
+  "MPL-1.0",
+  "MPL-1.1",
+  "MPL-2.0",
+  "MS-PL",
+  "NCSA",
+  "OFL-1.1",
+  "OpenSSL",
+  "PostgreSQL",
+  "Python-2.0",
+  "QPL-1.0",
+  "Ruby",
+  "Unicode-DFS-2016",
+  "Unlicense",
+  "UPL-1.0",
+  "Vim",
+  "W3C",
+  "WTFPL",
+  "X11",
+  "Zlib",
+  "ZPL-2.1",
+  // deprecated identifiers, still common and accepted
+  "GPL-2.0",
+  "GPL-3.0",
+  "LGPL-2.0",
+  "LGPL-2.1",
+  "LGPL-3.0",
+  "AGPL-3.0",
+];
+
+/// Exceptions allowed after `WITH`.
+const SPDX_EXCEPTIONS: [&str; 8] = [
+  "OCaml-LGPL-linking-exception",
+  "LLVM-exception",
+  "Classpath-exception-2.0",
+  "GCC-exception-3.1",
+  "Autoconf-exception-3.0",
+  "Bison-exception-2.2",
+  "Font-exception-2.0",
+  "Linux-syscall-note",
+];
+
+/// Whether `str` is an SPDX license expression, like `LGPL-2.1-only WITH OCaml-LGPL-linking-exception`.
+fn is_spdx_expression(str: &str) -> bool {
+  let spaced = str.replace('(', " ( ").replace(')', " ) ");
+  let tokens = spaced.split_whitespace().collect::<Vec<_>>();
+  let mut pos = 0;
+  spdx_or(&tokens, &mut pos) && pos == tokens.len()
+}
+
+fn spdx_or(tokens: &[&str], pos: &mut usize) -> bool {
+  if !spdx_and(tokens, pos) {
+    return false;
+  }
+  while tokens.get(*pos) == Some(&"OR") {
+    *pos += 1;
+    if !spdx_and(tokens, pos) {
+      return false;
+    }
+  }
+  true
+}
+
+fn spdx_and(tokens: &[&str], pos: &mut usize) -> bool {
+  if !spdx_with(tokens, pos) {
+    return false;
+  }
+  while tokens.get(*pos) == Some(&"AND") {
+    *pos += 1;
+    if !spdx_with(tokens, pos) {
+      return false;
+    }
+  }
+  true
+}
+
+fn spdx_with(tokens: &[&str], pos: &mut usize) -> bool {
+  let valid = match tokens.get(*pos) {
+    Some(&"(") => {
+      *pos += 1;
+      let valid = spdx_or(tokens, pos) && tokens.get(*pos) == Some(&")");
+      *pos += 1;
+      return valid;
+    }
+    Some(id) => {
+      let id = id.strip_suffix('+').unwrap_or(id);
+      SPDX_LICENSES.contains(&id) || id.starts_with("LicenseRef-")
+    }
+    None => false,
+  };
+  *pos += 1;
+  if valid && tokens.get(*pos) == Some(&"WITH") {
+    *pos += 1;
+    let exception = tokens
+      .get(*pos)
+      .is_some_and(|e| SPDX_EXCEPTIONS.contains(e));
+    *pos += 1;
+    return exception;
+  }
+  valid
+}
@@ -0,0 +1,577 @@
+//! Typed model of the opam package file.
+//!
+//! See more [opam](https://opam.ocaml.org/doc/Manual.html#opam)
+//!
+//! ```rust
+//! use opam_file_rs::package::OpamPackage;
+//! let opam = opam_file_rs::parse(r#"
+//!   opam-version: "2.0"
+//!   name: "foo"
+//!   version: "0.1.0"
+//!   maintainer: "Foo <foo@example.com>"
+//!   depends: ["ocaml" {>= "4.08"} "dune" {build}]
+//!   build: ["dune" "build" "-p" name "-j" jobs]
+//!   x-ci-accept-failures: ["macos-homebrew"]
+//! "#).unwrap();
+//! let package = OpamPackage::from_opam_file(&opam).unwrap();
+//! assert_eq!(package.name.as_deref(), Some("foo"));
+//! assert_eq!(package.version.unwrap().to_string(), "0.1.0");
+//! assert_eq!(package.maintainer, ["Foo <foo@example.com>"]);
+//! assert_eq!(package.depends.unwrap().package_names(), ["ocaml", "dune"]);
+//! assert_eq!(package.build.len(), 1);
+//! assert_eq!(package.extra_fields[0].0, "x-ci-accept-failures");
+//! ```
+//!
+//! The fields can also be read and written directly on an [`OpamFile`],
+//! keeping the other fields and the order of the fields as they are:
+//!
+//! ```rust
+//! let mut opam = opam_file_rs::parse(r#"
+//!   opam-version: "2.0"
+//!   name: "foo"
+//!   version: "0.1.0"
+//!   x-maintenance-intent: ["(latest)"]
+//!   depends: ["dune"]
+//! "#).unwrap();
+//! assert_eq!(opam.name().unwrap().as_deref(), Some("foo"));
+//! opam.set_version(&"0.2.0".parse().unwrap());
+//! opam.set_maintainers(&["Foo <foo@example.com>".to_string()]);
+//! assert_eq!(
+//!   opam_file_rs::value::format_opam_file(&opam),
+//!   r#"opam-version : "2.0"
+//! name : "foo"
+//! version : "0.2.0"
+//! x-maintenance-intent : ["(latest)"]
+//! depends : ["dune"]
+//! maintainer : "Foo <foo@example.com>"
+//! "#
+//! );
+//! ```
+
+use thiserror::Error;
+
+use super::fields::{
+  commands, env_updates, fields, filtered_strings, invalid_type, section_fields, split_option,
+  string, string_pairs, strings,
+};
+use super::formula::{FormulaError, PackageFormula};
+use super::value::*;
+use super::version::{OpamVersion, VersionError};
+
+/// The fields of an opam file. A field that is absent is `None` or empty.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct OpamPackage {
+  /// `opam-version`
+  pub opam_version: Option<String>,
+  pub name: Option<String>,
+  pub version: Option<OpamVersion>,
+  pub maintainer: Vec<String>,
+  pub authors: Vec<String>,
+  pub license: Vec<String>,
+  pub homepage: Vec<String>,
+  pub doc: Vec<String>,
+  /// `bug-reports`
+  pub bug_reports: Vec<String>,
+  /// `dev-repo`
+  pub dev_repo: Option<String>,
+  pub tags: Vec<String>,
+  pub patches: Vec<FilteredString>,
+  pub substs: Vec<String>,
+  pub build: Vec<Command>,
+  pub install: Vec<Command>,
+  pub remove: Vec<Command>,
+  /// `run-test`
+  pub run_test: Vec<Command>,
+  /// `build-env`
+  pub build_env: Vec<EnvUpdate>,
+  pub setenv: Vec<EnvUpdate>,
+  pub depends: Option<PackageFormula>,
+  pub depopts: Option<PackageFormula>,
+  pub conflicts: Option<PackageFormula>,
+  /// `conflict-class`
+  pub conflict_class: Vec<String>,
+  /// A filter.
+  pub available: Option<Value>,
+  pub flags: Vec<String>,
+  pub depexts: Vec<Depext>,
+  pub messages: Vec<FilteredString>,
+  /// `post-messages`
+  pub post_messages: Vec<FilteredString>,
+  pub synopsis: Option<String>,
+  pub description: Option<String>,
+  /// The `url { }` section.
+  pub url: Option<Url>,
+  /// The `extra-source "name" { }` sections.
+  pub extra_sources: Vec<ExtraSource>,
+  /// `extra-files`: file names and their checksums.
+  pub extra_files: Vec<(String, String)>,
+  /// `pin-depends`: packages, as `name.version`, and their URLs.
+  pub pin_depends: Vec<(String, String)>,
+  /// The `x-` extension fields, and the fields not listed above, such as deprecated ones, in order.
+  pub extra_fields: Vec<(String, Value)>,
+}
+
+/// A string with an optional filter, like `"msg" {os = "macos"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FilteredString {
+  pub value: String,
+  pub filter: Option<Value>,
+}
+
+/// A command of `build:`, `install:`, etc., like `["make" "PREFIX=%{prefix}%" {os != "win32"}] {with-test}`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Command {
+  pub args: Vec<Argument>,
+  pub filter: Option<Value>,
+}
+
+/// An argument of a command.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Argument {
+  pub kind: ArgumentKind,
+  pub filter: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ArgumentKind {
+  /// A string, possibly with interpolations.
+  String(String),
+  /// A variable whose value is the argument, like `name` or `jobs`.
+  Ident(String),
+}
+
+/// An update of an environment variable, like `PATH += "%{bin}%"`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EnvUpdate {
+  pub variable: String,
+  pub op: EnvUpdateOpKind,
+  pub value: String,
+}
+
+/// System packages, like `["libgmp-dev"] {os-family = "debian"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Depext {
+  pub packages: Vec<String>,
+  pub filter: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Url {
+  pub src: Option<String>,
+  pub checksum: Vec<String>,
+  pub mirrors: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExtraSource {
+  /// The file name the source is saved as.
+  pub name: String,
+  pub src: Option<String>,
+  pub checksum: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Error)]
+pub enum PackageError {
+  /// The value of the field does not have the expected type.
+  #[error("field `{0}` must be {1}, found `{2}` at {}", .3.0)]
+  InvalidType(String, &'static str, String, Pos),
+  #[error("duplicate field `{0}` at {}", .1.0)]
+  DuplicateField(String, Pos),
+  #[error("unknown field `{0}` at {}", .1.0)]
+  UnknownField(String, Pos),
+  #[error("unknown section `{0}` at {}", .1.0)]
+  UnknownSection(String, Pos),
+  #[error("section `{0}` must have a name at {}", .1.0)]
+  UnnamedSection(String, Pos),
+  /// A file of the `misc` section of an `.install` file must have an absolute destination.
+  #[error("file `{0}` of `misc` must have an absolute destination at {}", .1.0)]
+  MissingDestination(String, Pos),
+  #[error("{0} at {}", .1.0)]
+  InvalidVersion(VersionError, Pos),
+  #[error("{0}")]
+  Formula(#[from] FormulaError),
+}
+
+impl OpamPackage {
+  pub fn from_opam_file(file: &OpamFile) -> Result<Self, PackageError> {
+    let mut package = OpamPackage::default();
+    for (_, name, value) in fields(file)? {
+      package.set_field(name, value)?;
+    }
+    for item in &file.file_contents {
+      if let OpamFileItem::Section(pos, section) = item {
+        match section.section_kind.as_str() {
+          "url" => {
+            if package.url.is_some() {
+              return Err(PackageError::DuplicateField("url".to_string(), *pos));
+            }
+            package.url = Some(url(&section.section_item)?)
+          }
+          "extra-source" => {
+            let name = match &section.section_name {
+              Some(name) => name.clone(),
+              None => {
+                return Err(PackageError::UnnamedSection(
+                  "extra-source".to_string(),
+                  *pos,
+                ))
+              }
+            };
+            if package
+              .extra_sources
+              .iter()
+              .any(|source| source.name == name)
+            {
+              return Err(PackageError::DuplicateField(
+                format!("extra-source \"{}\"", name),
+                *pos,
+              ));
+            }
+            let url = url(&section.section_item)?;
+            package.extra_sources.push(ExtraSource {
+              name,
+              src: url.src,
+              checksum: url.checksum,
+            });
+          }
+          kind => return Err(PackageError::UnknownSection(kind.to_string(), *pos)),
+        }
+      }
+    }
+    Ok(package)
+  }
+
+  pub(crate) fn set_field(&mut self, name: &str, value: &Value) -> Result<(), PackageError> {
+    match name {
+      "opam-version" => self.opam_version = Some(string(name, value)?),
+      "name" => self.name = Some(string(name, value)?),
+      "version" => {
+        let version = string(name, value)?
+          .parse()
+          .map_err(|err| PackageError::InvalidVersion(err, value.pos))?;
+        self.version = Some(version)
+      }
+      "maintainer" => self.maintainer = strings(name, value)?,
+      "authors" => self.authors = strings(name, value)?,
+      "license" => self.license = strings(name, value)?,
+      "homepage" => self.homepage = strings(name, value)?,
+      "doc" => self.doc = strings(name, value)?,
+      "bug-reports" => self.bug_reports = strings(name, value)?,
+      "dev-repo" => self.dev_repo = Some(string(name, value)?),
+      "tags" => self.tags = strings(name, value)?,
+      "patches" => self.patches = filtered_strings(name, value)?,
+      "substs" => self.substs = strings(name, value)?,
+      "build" => self.build = commands(name, value)?,
+      "install" => self.install = commands(name, value)?,
+      "remove" => self.remove = commands(name, value)?,
+      "run-test" => self.run_test = commands(name, value)?,
+      "build-env" => self.build_env = env_updates(name, value)?,
+      "setenv" => self.setenv = env_updates(name, value)?,
+      "depends" => self.depends = Some(PackageFormula::from_value(value)?),
+      "depopts" => self.depopts = Some(PackageFormula::from_value(value)?),
+      "conflicts" => self.conflicts = Some(PackageFormula::from_value(value)?),
+      "conflict-class" => self.conflict_class = strings(name, value)?,
+      "available" => self.available = Some(value.clone()),
+      "flags" => self.flags = idents(name, value)?,
+      "depexts" => self.depexts = depexts(name, value)?,
+      "messages" => self.messages = filtered_strings(name, value)?,
+      "post-messages" => self.post_messages = filtered_strings(name, value)?,
+      "synopsis" => self.synopsis = Some(string(name, value)?),
+      "description" => self.description = Some(string(name, value)?),
+      "extra-files" => self.extra_files = string_pairs(name, value)?,
+      "pin-depends" => self.pin_depends = string_pairs(name, value)?,
+      _ => self.extra_fields.push((name.to_string(), value.clone())),
+    }
+    Ok(())
+  }
+}
+
+impl FilteredString {
+  /// The string as `"value" {filter}`.
+  pub fn to_value(&self) -> Value {
+    with_filter(
+      make_value(ValueKind::String(self.value.clone())),
+      &self.filter,
+    )
+  }
+}
+
+impl Command {
+  /// The command as `[args] {filter}`.
+  pub fn to_value(&self) -> Value {
+    let args = self
+      .args
+      .iter()
+      .map(|arg| {
+        let kind = match &arg.kind {
+          ArgumentKind::String(str) => ValueKind::String(str.clone()),
+          ArgumentKind::Ident(str) => ValueKind::Ident(str.clone()),
+        };
+        with_filter(make_value(kind), &arg.filter)
+      })
+      .collect();
+    with_filter(make_value(ValueKind::List(args)), &self.filter)
+  }
+}
+
+impl EnvUpdate {
+  pub fn to_value(&self) -> Value {
+    make_value(ValueKind::EnvBinding(
+      Box::new(make_value(ValueKind::Ident(self.variable.clone()))),
+      EnvUpdateOp {
+        kind: self.op.clone(),
+        pos: (0, 0),
+      },
+      Box::new(make_value(ValueKind::String(self.value.clone()))),
+    ))
+  }
+}
+
+fn with_filter(value: Value, filter: &Option<Value>) -> Value {
+  match filter {
+    Some(filter) => make_value(ValueKind::Option(Box::new(value), vec![filter.clone()])),
+    None => value,
+  }
+}
+
+/// A list of idents, or a single ident.
+fn idents(field: &str, value: &Value) -> Result<Vec<String>, PackageError> {
+  let ident = |v: &Value| match &v.kind {
+    ValueKind::Ident(str) => Ok(str.clone()),
+    _ => Err(invalid_type(field, "a list of identifiers", v)),
+  };
+  match &value.kind {
+    ValueKind::List(lst) => lst.iter().map(ident).collect(),
+    _ => Ok(vec![ident(value)?]),
+  }
+}
+
+fn depexts(field: &str, value: &Value) -> Result<Vec<Depext>, PackageError> {
+  let depext = |v: &Value| {
+    let (packages, filter) = split_option(field, v)?;
+    Ok(Depext {
+      packages: strings(field, packages)?,
+      filter,
+    })
+  };
+  match &value.kind {
+    ValueKind::List(lst) => lst.iter().map(depext).collect(),
+    _ => Err(invalid_type(field, "a list of system packages", value)),
+  }
+}
+
+/// The fields of a `url { }` or `extra-source "name" { }` section.
+fn url(items: &[OpamFileItem]) -> Result<Url, PackageError> {
+  let mut url = Url::default();
+  for (pos, name, value) in section_fields(items)? {
+    match name {
+      // `archive` and `http` are older names of `src`
+      "src" | "archive" | "http" => url.src = Some(string(name, value)?),
+      "checksum" => url.checksum = strings(name, value)?,
+      "mirrors" => url.mirrors = strings(name, value)?,
+      _ => return Err(PackageError::UnknownField(name.to_string(), pos)),
+    }
+  }
+  Ok(url)
+}
+
+/// Accessors for the well-known fields of an opam file.
+///
+/// Getters return `Ok(None)` or an empty list if the field is absent, and an error if it has an invalid type.
+/// Setters change the value of the field in place, or append the field if it is absent.
+impl OpamFile {
+  /// The value of the top-level field `name`.
+  pub fn field(&self, name: &str) -> Option<&Value> {
+    self.file_contents.iter().find_map(|item| match item {
+      OpamFileItem::Variable(_, n, value) if n == name => Some(value),
+      _ => None,
+    })
+  }
+
+  /// Set the value of the top-level field `name`, keeping its place if it exists.
+  pub fn set_field(&mut self, name: &str, value: Value) {
+    for item in &mut self.file_contents {
+      if let OpamFileItem::Variable(_, n, v) = item {
+        if n == name {
+          *v = value;
+          return;
+        }
+      }
+    }
+    self
+      .file_contents
+      .push(OpamFileItem::Variable((0, 0), name.to_string(), value));
+  }
+
+  /// Remove the top-level field `name`, returning its value.
+  pub fn remove_field(&mut self, name: &str) -> Option<Value> {
+    let index = self
+      .file_contents
+      .iter()
+      .position(|item| matches!(item, OpamFileItem::Variable(_, n, _) if n == name))?;
+    match self.file_contents.remove(index) {
+      OpamFileItem::Variable(_, _, value) => Some(value),
+      OpamFileItem::Section(_, _) => unreachable!(),
+    }
+  }
+
+  fn string_field(&self, name: &str) -> Result<Option<String>, PackageError> {
+    self.field(name).map(|v| string(name, v)).transpose()
+  }
+
+  fn strings_field(&self, name: &str) -> Result<Vec<String>, PackageError> {
+    self
+      .field(name)
+      .map_or(Ok(Vec::new()), |v| strings(name, v))
+  }
+
+  fn formula_field(&self, name: &str) -> Result<Option<PackageFormula>, PackageError> {
+    Ok(
+      self
+        .field(name)
+        .map(PackageFormula::from_value)
+        .transpose()?,
+    )
+  }
+
+  fn set_string(&mut self, name: &str, str: &str) {
+    self.set_field(name, make_value(ValueKind::String(str.to_string())));
+  }
+
+  /// A single string is written as is, and other numbers of strings as a list.
+  fn set_strings(&mut self, name: &str, strs: &[String]) {
+    let value = match strs {
+      [str] => ValueKind::String(str.clone()),
+      _ => ValueKind::List(
+        strs
+          .iter()
+          .map(|str| make_value(ValueKind::String(str.clone())))
+          .collect(),
+      ),
+    };
+    self.set_field(name, make_value(value));
+  }
+
+  /// `opam-version`
+  pub fn opam_version(&self) -> Result<Option<String>, PackageError> {
+    self.string_field("opam-version")
+  }
+
+  pub fn set_opam_version(&mut self, opam_version: &str) {
+    self.set_string("opam-version", opam_version)
+  }
+
+  pub fn name(&self) -> Result<Option<String>, PackageError> {
+    self.string_field("name")
+  }
+
+  pub fn set_name(&mut self, name: &str) {
+    self.set_string("name", name)
+  }
+
+  pub fn version(&self) -> Result<Option<OpamVersion>, PackageError> {
+    match self.field("version") {
+      Some(value) => {
+        let version = string("version", value)?
+          .parse()
+          .map_err(|err| PackageError::InvalidVersion(err, value.pos))?;
+        Ok(Some(version))
+      }
+      None => Ok(None),
+    }
+  }
+
+  pub fn set_version(&mut self, version: &OpamVersion) {
+    self.set_string("version", version.as_str())
+  }
+
+  pub fn synopsis(&self) -> Result<Option<String>, PackageError> {
+    self.string_field("synopsis")
+  }
+
+  pub fn set_synopsis(&mut self, synopsis: &str) {
+    self.set_string("synopsis", synopsis)
+  }
+
+  pub fn description(&self) -> Result<Option<String>, PackageError> {
+    self.string_field("description")
+  }
+
+  pub fn set_description(&mut self, description: &str) {
+    self.set_string("description", description)
+  }
+
+  /// `dev-repo`
+  pub fn dev_repo(&self) -> Result<Option<String>, PackageError> {
+    self.string_field("dev-repo")
+  }
+
+  pub fn set_dev_repo(&mut self, dev_repo: &str) {
+    self.set_string("dev-repo", dev_repo)
+  }
+
+  /// `maintainer`
+  pub fn maintainers(&self) -> Result<Vec<String>, PackageError> {
+    self.strings_field("maintainer")
+  }
+
+  pub fn set_maintainers(&mut self, maintainers: &[String]) {
+    self.set_strings("maintainer", maintainers)
+  }
+
+  pub fn authors(&self) -> Result<Vec<String>, PackageError> {
+    self.strings_field("authors")
+  }
+
+  pub fn set_authors(&mut self, authors: &[String]) {
+    self.set_strings("authors", authors)
+  }
+
+  pub fn licenses(&self) -> Result<Vec<String>, PackageError> {
+    self.strings_field("license")
+  }
+
+  pub fn set_licenses(&mut self, licenses: &[String]) {
+    self.set_strings("license", licenses)
+  }
+
+  pub fn homepages(&self) -> Result<Vec<String>, PackageError> {
+    self.strings_field("homepage")
+  }
+
+  pub fn set_homepages(&mut self, homepages: &[String]) {
+    self.set_strings("homepage", homepages)
+  }
+
+  pub fn tags(&self) -> Result<Vec<String>, PackageError> {
+    self.strings_field("tags")
+  }
+
+  pub fn set_tags(&mut self, tags: &[String]) {
+    self.set_strings("tags", tags)
+  }
+
+  pub fn depends(&self) -> Result<Option<PackageFormula>, PackageError> {
+    self.formula_field("depends")
+  }
+
+  pub fn set_depends(&mut self, depends: &PackageFormula) {
+    self.set_field("depends", depends.to_value())
+  }
+
+  pub fn depopts(&self) -> Result<Option<PackageFormula>, PackageError> {
+    self.formula_field("depopts")
+  }
+
+  pub fn set_depopts(&mut self, depopts: &PackageFormula) {
+    self.set_field("depopts", depopts.to_value())
+  }
+
+  pub fn conflicts(&self) -> Result<Option<PackageFormula>, PackageError> {
+    self.formula_field("conflicts")
+  }
+
+  pub fn set_conflicts(&mut self, conflicts: &PackageFormula) {
+    self.set_field("conflicts", conflicts.to_value())
+  }
+}
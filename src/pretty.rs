@@ -0,0 +1,252 @@
+//! Pretty-printer producing the layout of files written by opam itself.
+//!
+//! - items are written as `field: value`, one per line, and sections as `kind "name" {` ... `}`
+//! - a list is written on one line if it fits in the line width,
+//!   and otherwise with one element per line, indented by two spaces
+//! - the list of a field is always written one element per line
+//!   if it contains a list or a value with a filter,
+//!   or if the field is a package formula or a list of commands, like `depends:` and `build:`
+//!
+//! ```rust
+//! let opam = opam_file_rs::parse(r#"
+//!   opam-version: "2.0" name: "foo" tags: ["a" "b"]
+//!   depends: ["ocaml" {>= "4.08"} "dune"]
+//!   build: [["dune" "build" "-p" name "-j" jobs]]
+//! "#).unwrap();
+//! let config = opam_file_rs::pretty::Config::default();
+//! assert_eq!(
+//!   opam_file_rs::pretty::format_opam_file(&opam, &config),
+//!   r#"opam-version: "2.0"
+//! name: "foo"
+//! tags: ["a" "b"]
+//! depends: [
+//!   "ocaml" {>= "4.08"}
+//!   "dune"
+//! ]
+//! build: [
+//!   ["dune" "build" "-p" name "-j" jobs]
+//! ]
+//! "#
+//! );
+//! ```
+
+use super::value::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Config {
+  /// Lists that would make a line longer than this are broken into one element per line.
+  pub width: usize,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config { width: 80 }
+  }
+}
+
+/// Convert to an OPAM file format, laid out as opam does.
+pub fn format_opam_file(input: &OpamFile, config: &Config) -> String {
+  items_to_string(&input.file_contents, 0, config)
+}
+
+fn items_to_string(items: &[OpamFileItem], indent: usize, config: &Config) -> String {
+  items
+    .iter()
+    .map(|item| {
+      format!(
+        "{}{}\n",
+        " ".repeat(indent),
+        item_to_string(item, indent, config)
+      )
+    })
+    .collect::<String>()
+}
+
+fn item_to_string(item: &OpamFileItem, indent: usize, config: &Config) -> String {
+  match item {
+    OpamFileItem::Variable(_, name, value) => {
+      let column = indent + name.chars().count() + 2;
+      let value_str = match &value.kind {
+        ValueKind::List(lst)
+          if !lst.is_empty()
+            && (VERTICAL_FIELDS.contains(&name.as_str())
+              || lst.iter().any(is_vertical_element)) =>
+        {
+          vertical_to_string("[", lst, "]", indent, config)
+        }
+        _ => value_to_string(value, indent, column, config),
+      };
+      format!("{}: {}", name, value_str)
+    }
+    OpamFileItem::Section(_, section) => {
+      let name = match &section.section_name {
+        Some(name) => format!(" {}", string_literal(name)),
+        None => String::new(),
+      };
+      format!(
+        "{}{} {{\n{}{}}}",
+        section.section_kind,
+        name,
+        items_to_string(&section.section_item, indent + 2, config),
+        " ".repeat(indent)
+      )
+    }
+  }
+}
+
+/// Fields whose list is written one element per line, whatever its elements.
+const VERTICAL_FIELDS: &[&str] = &[
+  "depends",
+  "depopts",
+  "conflicts",
+  "pin-depends",
+  "build",
+  "install",
+  "remove",
+  "run-test",
+  "build-doc",
+  "build-test",
+];
+
+/// Elements that make the list of a field be written one element per line.
+fn is_vertical_element(value: &Value) -> bool {
+  match &value.kind {
+    ValueKind::List(_) => true,
+    ValueKind::Option(_, filter) => !filter.is_empty(),
+    _ => false,
+  }
+}
+
+/// `value` starting at `column`, on a line indented by `indent`.
+fn value_to_string(value: &Value, indent: usize, column: usize, config: &Config) -> String {
+  match &value.kind {
+    ValueKind::Bool(b) => b.to_string(),
+    ValueKind::Int(i) => i.to_string(),
+    ValueKind::String(str) => string_literal(str),
+    ValueKind::Ident(str) => str.to_string(),
+    ValueKind::List(lst) => hv_to_string("[", lst, "]", indent, column, config),
+    ValueKind::Group(lst) => hv_to_string("(", lst, ")", indent, column, config),
+    ValueKind::Option(v, lst) => {
+      let v_str = operand_to_string(v, Precedence::Option, indent, column, config);
+      format!("{} {{{}}}", v_str, flat_values_to_string(lst))
+    }
+    ValueKind::RelOp(op, l, r) => format!(
+      "{} {} {}",
+      flat_operand_to_string(l, Precedence::Atom),
+      relop_to_string(&op.kind),
+      flat_operand_to_string(r, Precedence::Atom)
+    ),
+    ValueKind::PrefixRelOp(op, r) => format!(
+      "{} {}",
+      relop_to_string(&op.kind),
+      flat_operand_to_string(r, Precedence::Atom)
+    ),
+    ValueKind::LogOp(op, l, r) => {
+      let (l_prec, r_prec) = logop_operand_precedences(&op.kind);
+      format!(
+        "{} {} {}",
+        flat_operand_to_string(l, l_prec),
+        logop_to_string(&op.kind),
+        flat_operand_to_string(r, r_prec)
+      )
+    }
+    ValueKind::PfxOp(op, r) => {
+      let r_str = flat_operand_to_string(r, Precedence::Prefix);
+      // `! =` must not be read back as `!=`
+      let space = if r_str.starts_with('=') { " " } else { "" };
+      format!("{}{}{}", pfxop_to_string(&op.kind), space, r_str)
+    }
+    ValueKind::EnvBinding(l, op, r) => format!(
+      "{} {} {}",
+      flat_operand_to_string(l, Precedence::Atom),
+      envop_to_string(&op.kind),
+      flat_operand_to_string(r, Precedence::Atom)
+    ),
+  }
+}
+
+fn operand_to_string(
+  value: &Value,
+  min: Precedence,
+  indent: usize,
+  column: usize,
+  config: &Config,
+) -> String {
+  if precedence(value) < min {
+    format!("({})", value_to_string(value, indent, column + 1, config))
+  } else {
+    value_to_string(value, indent, column, config)
+  }
+}
+
+fn flat_operand_to_string(value: &Value, min: Precedence) -> String {
+  operand_to_string(value, min, 0, 0, &flat_config())
+}
+
+/// A configuration under which everything fits on one line.
+fn flat_config() -> Config {
+  Config { width: usize::MAX }
+}
+
+fn flat_values_to_string(lst: &[Value]) -> String {
+  let config = flat_config();
+  lst
+    .iter()
+    .enumerate()
+    .map(|(i, value)| element_to_string(i, value, 0, 0, &config))
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+fn element_to_string(
+  i: usize,
+  value: &Value,
+  indent: usize,
+  column: usize,
+  config: &Config,
+) -> String {
+  // `a >= b` is read back as a binary relop, so `>= b` must be parenthesized after another value
+  if i > 0 && starts_with_relop(value) {
+    format!("({})", value_to_string(value, indent, column + 1, config))
+  } else {
+    value_to_string(value, indent, column, config)
+  }
+}
+
+/// On one line if it fits, one element per line otherwise.
+fn hv_to_string(
+  open: &str,
+  lst: &[Value],
+  close: &str,
+  indent: usize,
+  column: usize,
+  config: &Config,
+) -> String {
+  let flat = format!("{}{}{}", open, flat_values_to_string(lst), close);
+  if lst.is_empty() || column.saturating_add(flat.chars().count()) <= config.width {
+    flat
+  } else {
+    vertical_to_string(open, lst, close, indent, config)
+  }
+}
+
+fn vertical_to_string(
+  open: &str,
+  lst: &[Value],
+  close: &str,
+  indent: usize,
+  config: &Config,
+) -> String {
+  let elements = lst
+    .iter()
+    .enumerate()
+    .map(|(i, value)| {
+      format!(
+        "{}{}\n",
+        " ".repeat(indent + 2),
+        element_to_string(i, value, indent + 2, indent + 2, config)
+      )
+    })
+    .collect::<String>();
+  format!("{}\n{}{}{}", open, elements, " ".repeat(indent), close)
+}
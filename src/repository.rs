@@ -0,0 +1,164 @@
+//! Files of an opam repository: the `repo` file at its root,
+//! and the `url` and `descr` files that packages of the 1.2 format have besides their `opam` file.
+//!
+//! See more [repo](https://opam.ocaml.org/doc/Manual.html#repo)
+//! and [Package definitions](https://opam.ocaml.org/doc/1.2/Manual.html#Package-definitions)
+//!
+//! ```rust
+//! use opam_file_rs::repository::{parse_descr, parse_url_file, RepoFile};
+//! let repo = opam_file_rs::parse(r#"
+//!   opam-version: "2.0"
+//!   browse: "https://opam.ocaml.org/pkg/"
+//!   upstream: "https://github.com/ocaml/opam-repository/tree/master/"
+//!   announce: ["Welcome" {opam-version >= "2.1"}]
+//! "#).unwrap();
+//! let repo = RepoFile::from_opam_file(&repo).unwrap();
+//! assert_eq!(repo.browse.as_deref(), Some("https://opam.ocaml.org/pkg/"));
+//! assert!(repo.announce[0].filter.is_some());
+//!
+//! let url = opam_file_rs::parse(r#"
+//!   git: "https://github.com/foo/foo.git"
+//! "#).unwrap();
+//! let url = parse_url_file(&url).unwrap();
+//! assert_eq!(url.src.as_deref(), Some("git+https://github.com/foo/foo.git"));
+//!
+//! let descr = parse_descr("A library for foo\n\nFoo does things.\n");
+//! assert_eq!(descr.synopsis, "A library for foo");
+//! assert_eq!(descr.description, "Foo does things.");
+//! ```
+
+use std::fmt;
+
+use super::fields::{fields, filtered_strings, no_sections, string, strings, Fields};
+use super::package::{FilteredString, PackageError, Url};
+use super::value::*;
+
+/// The fields of the `repo` file of a repository. A field that is absent is `None` or empty.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct RepoFile {
+  /// `opam-version`
+  pub opam_version: Option<String>,
+  /// The URL to browse the packages of the repository.
+  pub browse: Option<String>,
+  /// The URL of the sources of the repository, to which the paths of the package files are appended.
+  pub upstream: Option<String>,
+  /// URLs the repository moved to, with the filters on when to follow them.
+  pub redirect: Vec<FilteredString>,
+  /// Messages shown on updates, with the filters on when to show them.
+  pub announce: Vec<FilteredString>,
+  /// `dl-cache`
+  pub dl_cache: Vec<String>,
+  pub stamp: Option<String>,
+  /// The fields not listed above, in order.
+  pub extra_fields: Vec<(String, Value)>,
+}
+
+/// The `descr` file of a package: a synopsis on the first line, and a description on the following ones.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Descr {
+  pub synopsis: String,
+  pub description: String,
+}
+
+impl RepoFile {
+  pub fn from_opam_file(file: &OpamFile) -> Result<Self, PackageError> {
+    let mut repo = RepoFile::default();
+    for (_, name, value) in fields(file)? {
+      match name {
+        "opam-version" => repo.opam_version = Some(string(name, value)?),
+        "browse" => repo.browse = Some(string(name, value)?),
+        "upstream" => repo.upstream = Some(string(name, value)?),
+        "redirect" => repo.redirect = filtered_strings(name, value)?,
+        "announce" => repo.announce = filtered_strings(name, value)?,
+        "dl-cache" => repo.dl_cache = strings(name, value)?,
+        "stamp" => repo.stamp = Some(string(name, value)?),
+        _ => repo.extra_fields.push((name.to_string(), value.clone())),
+      }
+    }
+    no_sections(file)?;
+    Ok(repo)
+  }
+
+  /// An opam file with the fields that are set, in the order of the struct, followed by the extra fields.
+  pub fn to_opam_file(&self) -> OpamFile {
+    let mut fields = Fields::default();
+    fields.string("opam-version", &self.opam_version);
+    fields.string("browse", &self.browse);
+    fields.string("upstream", &self.upstream);
+    let filtered_strings = |strs: &[FilteredString]| strs.iter().map(|s| s.to_value()).collect();
+    fields.list("redirect", filtered_strings(&self.redirect));
+    fields.list("announce", filtered_strings(&self.announce));
+    fields.strings("dl-cache", &self.dl_cache);
+    fields.string("stamp", &self.stamp);
+    fields.extra(&self.extra_fields);
+    OpamFile {
+      file_contents: fields.0,
+    }
+  }
+}
+
+/// Read the `url` file of a package of the 1.2 format, as the `url { }` section of the 2.0 format.
+///
+/// The version control system of the `git`, `hg` and `darcs` fields is prepended to their URL,
+/// like `git+https://...`, and a checksum without a kind is an MD5 one.
+/// A field the `url { }` section cannot hold is an error.
+pub fn parse_url_file(file: &OpamFile) -> Result<Url, PackageError> {
+  let mut url = Url::default();
+  for (pos, name, value) in fields(file)? {
+    match name {
+      "src" | "archive" | "http" | "local" => url.src = Some(string(name, value)?),
+      "git" | "hg" | "darcs" => {
+        let src = string(name, value)?;
+        url.src = Some(
+          if src.starts_with(&format!("{}+", name)) || src.starts_with(&format!("{}://", name)) {
+            src
+          } else {
+            format!("{}+{}", name, src)
+          },
+        )
+      }
+      "checksum" => {
+        url.checksum = strings(name, value)?
+          .into_iter()
+          .map(|checksum| {
+            if checksum.contains('=') {
+              checksum
+            } else {
+              format!("md5={}", checksum)
+            }
+          })
+          .collect()
+      }
+      "mirrors" => url.mirrors = strings(name, value)?,
+      _ => return Err(PackageError::UnknownField(name.to_string(), pos)),
+    }
+  }
+  no_sections(file)?;
+  Ok(url)
+}
+
+/// Read a `descr` file, which is plain text and not in the opam syntax.
+///
+/// The blank lines between the synopsis and the description, and at the end, are not part of them.
+pub fn parse_descr(input: &str) -> Descr {
+  let (synopsis, description) = input.split_once('\n').unwrap_or((input, ""));
+  Descr {
+    synopsis: synopsis.trim().to_string(),
+    description: description
+      .trim_start_matches(['\n', '\r'])
+      .trim_end()
+      .to_string(),
+  }
+}
+
+impl fmt::Display for Descr {
+  /// The contents of a `descr` file.
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "{}", self.synopsis)?;
+    if !self.description.is_empty() {
+      writeln!(f)?;
+      writeln!(f, "{}", self.description)?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,348 @@
+//! Typed models of the `config` file of the opam root and the `switch-config` file of a switch.
+//!
+//! See more [config](https://opam.ocaml.org/doc/Manual.html#config)
+//! and [switch-config](https://opam.ocaml.org/doc/Manual.html#switch-config)
+//!
+//! ```rust
+//! use opam_file_rs::root::RootConfig;
+//! let opam = opam_file_rs::parse(r#"
+//!   opam-version: "2.0"
+//!   opam-root-version: "2.1"
+//!   repositories: "default"
+//!   installed-switches: ["5.1.1" "default"]
+//!   switch: "default"
+//!   jobs: 8
+//!   global-variables: [
+//!     [sys-ocaml-version "5.1.1" "OCaml version present on your system"]
+//!   ]
+//!   eval-variables: [
+//!     [sys-ocaml-arch ["ocamlfind" "ocamlc" "-config-var" "architecture"] "Target architecture"]
+//!   ]
+//! "#).unwrap();
+//! let mut config = RootConfig::from_opam_file(&opam).unwrap();
+//! assert_eq!(config.installed_switches, ["5.1.1", "default"]);
+//! assert_eq!(config.global_variables[0].name, "sys-ocaml-version");
+//! config.switch = Some("5.1.1".to_string());
+//! config.global_variables.clear();
+//! config.eval_variables.clear();
+//! assert_eq!(
+//!   opam_file_rs::value::format_opam_file(&config.to_opam_file()),
+//!   r#"opam-version : "2.0"
+//! opam-root-version : "2.1"
+//! repositories : ["default"]
+//! installed-switches : ["5.1.1" "default"]
+//! switch : "5.1.1"
+//! jobs : 8
+//! "#
+//! );
+//! ```
+
+use super::config::{variables, ConfigValue};
+use super::fields::{
+  bool, command, commands, env_updates, fields, int, invalid_type, no_sections, section_fields,
+  string, strings, strings_value, Fields,
+};
+use super::formula::PackageFormula;
+use super::package::{Command, EnvUpdate, PackageError};
+use super::value::*;
+
+/// The fields of `~/.opam/config`. A field that is absent is `None` or empty.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct RootConfig {
+  /// `opam-version`
+  pub opam_version: Option<String>,
+  /// `opam-root-version`
+  pub opam_root_version: Option<String>,
+  /// The names of the repositories, by decreasing priority.
+  pub repositories: Vec<String>,
+  /// `installed-switches`
+  pub installed_switches: Vec<String>,
+  /// The current switch.
+  pub switch: Option<String>,
+  pub jobs: Option<usize>,
+  /// `download-command`
+  pub download_command: Option<Command>,
+  /// `download-jobs`
+  pub download_jobs: Option<usize>,
+  /// `archive-mirrors`
+  pub archive_mirrors: Vec<String>,
+  /// `solver-criteria`
+  pub solver_criteria: Option<String>,
+  /// `solver-upgrade-criteria`
+  pub solver_upgrade_criteria: Option<String>,
+  /// `solver-fixup-criteria`
+  pub solver_fixup_criteria: Option<String>,
+  pub solver: Option<Command>,
+  /// `wrap-build-commands`
+  pub wrap_build_commands: Vec<Command>,
+  /// `wrap-install-commands`
+  pub wrap_install_commands: Vec<Command>,
+  /// `wrap-remove-commands`
+  pub wrap_remove_commands: Vec<Command>,
+  /// `global-variables`
+  pub global_variables: Vec<GlobalVariable>,
+  /// `eval-variables`
+  pub eval_variables: Vec<EvalVariable>,
+  /// `default-compiler`
+  pub default_compiler: Option<PackageFormula>,
+  /// `default-invariant`
+  pub default_invariant: Option<PackageFormula>,
+  pub depext: Option<bool>,
+  /// `depext-run-installs`
+  pub depext_run_installs: Option<bool>,
+  /// `depext-cannot-install`
+  pub depext_cannot_install: Option<bool>,
+  /// `depext-bypass`
+  pub depext_bypass: Vec<String>,
+  /// The fields not listed above, in order.
+  pub extra_fields: Vec<(String, Value)>,
+}
+
+/// A variable of `global-variables`, like `[sys-ocaml-version "5.1.1" "OCaml version present on your system"]`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GlobalVariable {
+  pub name: String,
+  pub value: ConfigValue,
+  pub doc: String,
+}
+
+/// A variable of `eval-variables`, whose value is the output of `command`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct EvalVariable {
+  pub name: String,
+  pub command: Command,
+  pub doc: String,
+}
+
+/// The fields of `<switch>/.opam-switch/switch-config`. A field that is absent is `None` or empty.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct SwitchConfig {
+  /// `opam-version`
+  pub opam_version: Option<String>,
+  pub synopsis: Option<String>,
+  /// The repositories of the switch, or `None` to use the ones of the opam root.
+  pub repositories: Option<Vec<String>>,
+  /// `opam-root`
+  pub opam_root: Option<String>,
+  pub setenv: Vec<EnvUpdate>,
+  pub invariant: Option<PackageFormula>,
+  /// `depext-bypass`
+  pub depext_bypass: Vec<String>,
+  /// `wrap-build-commands`
+  pub wrap_build_commands: Vec<Command>,
+  /// `wrap-install-commands`
+  pub wrap_install_commands: Vec<Command>,
+  /// `wrap-remove-commands`
+  pub wrap_remove_commands: Vec<Command>,
+  /// The `paths { }` section, overriding the directories of the switch, like `bin: "/usr/local/bin"`, in order.
+  pub paths: Vec<(String, String)>,
+  /// The `variables { }` section, in order.
+  pub variables: Vec<(String, ConfigValue)>,
+  /// The fields not listed above, in order.
+  pub extra_fields: Vec<(String, Value)>,
+}
+
+impl RootConfig {
+  pub fn from_opam_file(file: &OpamFile) -> Result<Self, PackageError> {
+    let mut config = RootConfig::default();
+    for (_, name, value) in fields(file)? {
+      match name {
+        "opam-version" => config.opam_version = Some(string(name, value)?),
+        "opam-root-version" => config.opam_root_version = Some(string(name, value)?),
+        "repositories" => config.repositories = strings(name, value)?,
+        "installed-switches" => config.installed_switches = strings(name, value)?,
+        "switch" => config.switch = Some(string(name, value)?),
+        "jobs" => config.jobs = Some(int(name, value)?),
+        "download-command" => config.download_command = Some(command(name, value)?),
+        "download-jobs" => config.download_jobs = Some(int(name, value)?),
+        "archive-mirrors" => config.archive_mirrors = strings(name, value)?,
+        "solver-criteria" => config.solver_criteria = Some(string(name, value)?),
+        "solver-upgrade-criteria" => config.solver_upgrade_criteria = Some(string(name, value)?),
+        "solver-fixup-criteria" => config.solver_fixup_criteria = Some(string(name, value)?),
+        "solver" => config.solver = Some(command(name, value)?),
+        "wrap-build-commands" => config.wrap_build_commands = commands(name, value)?,
+        "wrap-install-commands" => config.wrap_install_commands = commands(name, value)?,
+        "wrap-remove-commands" => config.wrap_remove_commands = commands(name, value)?,
+        "global-variables" => config.global_variables = global_variables(name, value)?,
+        "eval-variables" => config.eval_variables = eval_variables(name, value)?,
+        "default-compiler" => config.default_compiler = Some(PackageFormula::from_value(value)?),
+        "default-invariant" => config.default_invariant = Some(PackageFormula::from_value(value)?),
+        "depext" => config.depext = Some(bool(name, value)?),
+        "depext-run-installs" => config.depext_run_installs = Some(bool(name, value)?),
+        "depext-cannot-install" => config.depext_cannot_install = Some(bool(name, value)?),
+        "depext-bypass" => config.depext_bypass = strings(name, value)?,
+        _ => config.extra_fields.push((name.to_string(), value.clone())),
+      }
+    }
+    no_sections(file)?;
+    Ok(config)
+  }
+
+  /// An opam file with the fields that are set, in the order of the struct, followed by the extra fields.
+  pub fn to_opam_file(&self) -> OpamFile {
+    let mut fields = Fields::default();
+    fields.string("opam-version", &self.opam_version);
+    fields.string("opam-root-version", &self.opam_root_version);
+    fields.strings("repositories", &self.repositories);
+    fields.strings("installed-switches", &self.installed_switches);
+    fields.string("switch", &self.switch);
+    fields.int("jobs", self.jobs);
+    fields.command("download-command", &self.download_command);
+    fields.int("download-jobs", self.download_jobs);
+    fields.strings("archive-mirrors", &self.archive_mirrors);
+    fields.string("solver-criteria", &self.solver_criteria);
+    fields.string("solver-upgrade-criteria", &self.solver_upgrade_criteria);
+    fields.string("solver-fixup-criteria", &self.solver_fixup_criteria);
+    fields.command("solver", &self.solver);
+    fields.commands("wrap-build-commands", &self.wrap_build_commands);
+    fields.commands("wrap-install-commands", &self.wrap_install_commands);
+    fields.commands("wrap-remove-commands", &self.wrap_remove_commands);
+    let global_variables = self
+      .global_variables
+      .iter()
+      .map(|var| {
+        make_value(ValueKind::List(vec![
+          make_value(ValueKind::Ident(var.name.clone())),
+          var.value.to_value(),
+          make_value(ValueKind::String(var.doc.clone())),
+        ]))
+      })
+      .collect::<Vec<_>>();
+    fields.list("global-variables", global_variables);
+    let eval_variables = self
+      .eval_variables
+      .iter()
+      .map(|var| {
+        make_value(ValueKind::List(vec![
+          make_value(ValueKind::Ident(var.name.clone())),
+          var.command.to_value(),
+          make_value(ValueKind::String(var.doc.clone())),
+        ]))
+      })
+      .collect::<Vec<_>>();
+    fields.list("eval-variables", eval_variables);
+    fields.formula("default-compiler", &self.default_compiler);
+    fields.formula("default-invariant", &self.default_invariant);
+    fields.bool("depext", self.depext);
+    fields.bool("depext-run-installs", self.depext_run_installs);
+    fields.bool("depext-cannot-install", self.depext_cannot_install);
+    fields.strings("depext-bypass", &self.depext_bypass);
+    fields.extra(&self.extra_fields);
+    OpamFile {
+      file_contents: fields.0,
+    }
+  }
+}
+
+impl SwitchConfig {
+  pub fn from_opam_file(file: &OpamFile) -> Result<Self, PackageError> {
+    let mut config = SwitchConfig::default();
+    for (_, name, value) in fields(file)? {
+      match name {
+        "opam-version" => config.opam_version = Some(string(name, value)?),
+        "synopsis" => config.synopsis = Some(string(name, value)?),
+        "repositories" => config.repositories = Some(strings(name, value)?),
+        "opam-root" => config.opam_root = Some(string(name, value)?),
+        "setenv" => config.setenv = env_updates(name, value)?,
+        "invariant" => config.invariant = Some(PackageFormula::from_value(value)?),
+        "depext-bypass" => config.depext_bypass = strings(name, value)?,
+        "wrap-build-commands" => config.wrap_build_commands = commands(name, value)?,
+        "wrap-install-commands" => config.wrap_install_commands = commands(name, value)?,
+        "wrap-remove-commands" => config.wrap_remove_commands = commands(name, value)?,
+        _ => config.extra_fields.push((name.to_string(), value.clone())),
+      }
+    }
+    let mut seen: Vec<&str> = Vec::new();
+    for item in &file.file_contents {
+      if let OpamFileItem::Section(pos, section) = item {
+        let kind = section.section_kind.as_str();
+        if seen.contains(&kind) {
+          return Err(PackageError::DuplicateField(kind.to_string(), *pos));
+        }
+        seen.push(kind);
+        match kind {
+          "paths" => {
+            config.paths = section_fields(&section.section_item)?
+              .into_iter()
+              .map(|(_, name, value)| Ok((name.to_string(), string(name, value)?)))
+              .collect::<Result<_, PackageError>>()?
+          }
+          "variables" => config.variables = variables(&section.section_item)?,
+          _ => return Err(PackageError::UnknownSection(kind.to_string(), *pos)),
+        }
+      }
+    }
+    Ok(config)
+  }
+
+  /// An opam file with the fields that are set, in the order of the struct, followed by the extra fields.
+  pub fn to_opam_file(&self) -> OpamFile {
+    let mut fields = Fields::default();
+    fields.string("opam-version", &self.opam_version);
+    fields.string("synopsis", &self.synopsis);
+    if let Some(repositories) = &self.repositories {
+      fields.push("repositories", strings_value(repositories));
+    }
+    fields.string("opam-root", &self.opam_root);
+    let setenv = self.setenv.iter().map(EnvUpdate::to_value).collect();
+    fields.list("setenv", setenv);
+    fields.formula("invariant", &self.invariant);
+    fields.strings("depext-bypass", &self.depext_bypass);
+    fields.commands("wrap-build-commands", &self.wrap_build_commands);
+    fields.commands("wrap-install-commands", &self.wrap_install_commands);
+    fields.commands("wrap-remove-commands", &self.wrap_remove_commands);
+    fields.extra(&self.extra_fields);
+    let paths = self
+      .paths
+      .iter()
+      .map(|(name, path)| (name, make_value(ValueKind::String(path.clone()))));
+    fields.section("paths", paths);
+    let variables = self
+      .variables
+      .iter()
+      .map(|(name, value)| (name, value.to_value()));
+    fields.section("variables", variables);
+    OpamFile {
+      file_contents: fields.0,
+    }
+  }
+}
+
+/// A list of `[name value "doc"]`, or a single one.
+fn global_variables(field: &str, value: &Value) -> Result<Vec<GlobalVariable>, PackageError> {
+  variable_triples(field, value, |v| ConfigValue::from_value(field, v))?
+    .into_iter()
+    .map(|(name, value, doc)| Ok(GlobalVariable { name, value, doc }))
+    .collect()
+}
+
+/// A list of `[name [command] "doc"]`, or a single one.
+fn eval_variables(field: &str, value: &Value) -> Result<Vec<EvalVariable>, PackageError> {
+  variable_triples(field, value, |v| command(field, v))?
+    .into_iter()
+    .map(|(name, command, doc)| Ok(EvalVariable { name, command, doc }))
+    .collect()
+}
+
+fn variable_triples<T>(
+  field: &str,
+  value: &Value,
+  f: impl Fn(&Value) -> Result<T, PackageError>,
+) -> Result<Vec<(String, T, String)>, PackageError> {
+  let triple = |v: &Value| match &v.kind {
+    ValueKind::List(triple) => match triple.as_slice() {
+      [name, value, doc] => match &name.kind {
+        ValueKind::Ident(name) => Ok((name.clone(), f(value)?, string(field, doc)?)),
+        _ => Err(invalid_type(field, "a list of variables", name)),
+      },
+      _ => Err(invalid_type(field, "a list of variables", v)),
+    },
+    _ => Err(invalid_type(field, "a list of variables", v)),
+  };
+  match &value.kind {
+    ValueKind::List(lst) if lst.iter().all(|v| matches!(v.kind, ValueKind::List(_))) => {
+      lst.iter().map(triple).collect()
+    }
+    _ => Ok(vec![triple(value)?]),
+  }
+}
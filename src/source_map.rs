@@ -0,0 +1,128 @@
+//! Conversion of positions.
+//!
+//! A [`Pos`](crate::value::Pos) counts chars from the start of the source.
+//! [`SourceMap`] converts such positions to byte offsets, to slice the source `&str`,
+//! and to UTF-16 offsets and line/column pairs, as used by editors.
+//!
+//! ```rust
+//! let src = "name: \"caf\u{e9}\"\nversion: \"1\"";
+//! let (file, map) = opam_file_rs::parse_with_source_map(src).unwrap();
+//! let pos = match &file.file_contents[1] {
+//!   opam_file_rs::value::OpamFileItem::Variable(_, _, value) => value.pos,
+//!   _ => unreachable!(),
+//! };
+//! assert_eq!(&src[map.byte_range(pos).unwrap()], "\"1\"");
+//! let start = map.line_col(pos.0).unwrap();
+//! assert_eq!((start.line, start.column), (1, 9));
+//! ```
+
+use std::ops::Range;
+
+use super::value;
+
+/// A 0-origin line and column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash)]
+pub struct LineCol {
+  pub line: usize,
+  pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceMap {
+  /// Byte offset of each char, followed by the length of the source.
+  byte_offsets: Vec<usize>,
+  /// UTF-16 offset of each char, followed by the UTF-16 length of the source.
+  utf16_offsets: Vec<usize>,
+  /// Char position of the start of each line.
+  line_starts: Vec<usize>,
+}
+
+impl SourceMap {
+  pub fn new(input: &str) -> Self {
+    let mut byte_offsets = Vec::new();
+    let mut utf16_offsets = Vec::new();
+    let mut line_starts = vec![0];
+    let mut utf16_offset = 0;
+    for (pos, (byte_offset, c)) in input.char_indices().enumerate() {
+      byte_offsets.push(byte_offset);
+      utf16_offsets.push(utf16_offset);
+      utf16_offset += c.len_utf16();
+      if c == '\n' {
+        line_starts.push(pos + 1);
+      }
+    }
+    byte_offsets.push(input.len());
+    utf16_offsets.push(utf16_offset);
+    SourceMap {
+      byte_offsets,
+      utf16_offsets,
+      line_starts,
+    }
+  }
+
+  /// Number of chars in the source.
+  pub fn len(&self) -> usize {
+    self.byte_offsets.len() - 1
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Byte offset of the char at `pos`.
+  /// `pos` may be the end of the source; `None` if it is beyond.
+  pub fn byte_offset(&self, pos: usize) -> Option<usize> {
+    self.byte_offsets.get(pos).copied()
+  }
+
+  /// Byte range of `pos`, to slice the source with.
+  pub fn byte_range(&self, pos: value::Pos) -> Option<Range<usize>> {
+    Some(self.byte_offset(pos.0)?..self.byte_offset(pos.1)?)
+  }
+
+  /// UTF-16 offset of the char at `pos`.
+  pub fn utf16_offset(&self, pos: usize) -> Option<usize> {
+    self.utf16_offsets.get(pos).copied()
+  }
+
+  /// Line of the char at `pos`, and its column counted in chars.
+  pub fn line_col(&self, pos: usize) -> Option<LineCol> {
+    if pos > self.len() {
+      return None;
+    }
+    let line = self.line_starts.partition_point(|start| *start <= pos) - 1;
+    Some(LineCol {
+      line,
+      column: pos - self.line_starts[line],
+    })
+  }
+
+  /// Line of the char at `pos`, and its column counted in UTF-16 code units, as in the Language Server Protocol.
+  pub fn line_col_utf16(&self, pos: usize) -> Option<LineCol> {
+    let LineCol { line, .. } = self.line_col(pos)?;
+    let line_start = self.line_starts[line];
+    Some(LineCol {
+      line,
+      column: self.utf16_offsets[pos] - self.utf16_offsets[line_start],
+    })
+  }
+
+  /// Char position of the char starting at byte `offset`, or `None` if no char starts there.
+  pub fn pos_of_byte_offset(&self, offset: usize) -> Option<usize> {
+    self.byte_offsets.binary_search(&offset).ok()
+  }
+
+  /// Char position at `line` and UTF-16 `column`.
+  /// A column beyond the end of the line is clamped to the end of the line.
+  pub fn pos_of_line_col_utf16(&self, line_col: LineCol) -> Option<usize> {
+    let line_start = *self.line_starts.get(line_col.line)?;
+    let line_end = match self.line_starts.get(line_col.line + 1) {
+      Some(next) => next - 1,
+      None => self.len(),
+    };
+    let offset = self.utf16_offsets[line_start] + line_col.column;
+    let pos =
+      line_start + self.utf16_offsets[line_start..=line_end].partition_point(|o| *o < offset);
+    Some(pos.min(line_end))
+  }
+}
@@ -0,0 +1,236 @@
+//! Typed models of the `switch-state` file of a switch and of the output of `opam switch export`.
+//!
+//! See more [switch-state](https://opam.ocaml.org/doc/Manual.html#switch-state)
+//!
+//! ```rust
+//! use opam_file_rs::switch::SwitchState;
+//! let ours = opam_file_rs::parse(r#"
+//!   opam-version: "2.0"
+//!   compiler: ["ocaml-base-compiler.5.1.1"]
+//!   roots: ["dune.3.12.1" "ocaml-base-compiler.5.1.1"]
+//!   installed: ["dune.3.12.1" "ocaml.5.1.1" "ocaml-base-compiler.5.1.1"]
+//! "#).unwrap();
+//! let theirs = opam_file_rs::parse(r#"
+//!   opam-version: "2.0"
+//!   compiler: ["ocaml-base-compiler.5.1.1"]
+//!   roots: ["dune.3.11.0" "ocaml-base-compiler.5.1.1"]
+//!   installed: ["dune.3.11.0" "fmt.0.9.0" "ocaml.5.1.1" "ocaml-base-compiler.5.1.1"]
+//! "#).unwrap();
+//! let ours = SwitchState::from_opam_file(&ours).unwrap();
+//! let theirs = SwitchState::from_opam_file(&theirs).unwrap();
+//! assert_eq!(ours.installed[0].name, "dune");
+//! assert_eq!(ours.installed[0].version.to_string(), "3.12.1");
+//! let diff = ours.diff(&theirs);
+//! assert_eq!(diff.added[0].to_string(), "fmt.0.9.0");
+//! assert_eq!(diff.removed, []);
+//! assert_eq!(diff.changed[0].0.to_string(), "dune.3.12.1");
+//! assert_eq!(diff.changed[0].1.to_string(), "3.11.0");
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use super::fields::{fields, invalid_type, no_sections, string, Fields};
+use super::package::PackageError;
+use super::value::*;
+use super::version::{OpamVersion, VersionError};
+
+/// A package at a given version, written `name.version`, like `ocaml.5.1.1`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PackageVersion {
+  pub name: String,
+  pub version: OpamVersion,
+}
+
+/// The fields of `<switch>/.opam-switch/switch-state`. A field that is absent is `None` or empty.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct SwitchState {
+  /// `opam-version`
+  pub opam_version: Option<String>,
+  /// The packages of the compiler.
+  pub compiler: Vec<PackageVersion>,
+  /// The packages installed on request, as opposed to the ones installed as dependencies.
+  pub roots: Vec<PackageVersion>,
+  pub installed: Vec<PackageVersion>,
+  pub pinned: Vec<PackageVersion>,
+  /// The fields not listed above, in order.
+  pub extra_fields: Vec<(String, Value)>,
+}
+
+/// The output of `opam switch export`: a switch state, and the opam files of the pinned packages.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct SwitchExport {
+  pub state: SwitchState,
+  /// The `package "name" { }` sections, by package name.
+  pub packages: BTreeMap<String, OpamFile>,
+}
+
+/// The differences between the installed packages of two switches.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct SwitchDiff {
+  /// The packages installed only in the other switch.
+  pub added: Vec<PackageVersion>,
+  /// The packages installed only in this switch.
+  pub removed: Vec<PackageVersion>,
+  /// The packages installed in both switches at different versions, with the version of the other switch.
+  pub changed: Vec<(PackageVersion, OpamVersion)>,
+}
+
+impl FromStr for PackageVersion {
+  type Err = VersionError;
+  /// Package names contain no `.`, so the version is what follows the first one.
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let (name, version) = s.split_once('.').ok_or(VersionError::Empty)?;
+    Ok(PackageVersion {
+      name: name.to_string(),
+      version: version.parse()?,
+    })
+  }
+}
+
+impl fmt::Display for PackageVersion {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}.{}", self.name, self.version)
+  }
+}
+
+impl SwitchState {
+  pub fn from_opam_file(file: &OpamFile) -> Result<Self, PackageError> {
+    let mut state = SwitchState::default();
+    for (_, name, value) in fields(file)? {
+      state.set_field(name, value)?;
+    }
+    no_sections(file)?;
+    Ok(state)
+  }
+
+  /// An opam file with the fields that are set, in the order of the struct, followed by the extra fields.
+  pub fn to_opam_file(&self) -> OpamFile {
+    OpamFile {
+      file_contents: self.fields().0,
+    }
+  }
+
+  /// The package `name`, if it is installed.
+  pub fn installed_version(&self, name: &str) -> Option<&OpamVersion> {
+    self
+      .installed
+      .iter()
+      .find(|package| package.name == name)
+      .map(|package| &package.version)
+  }
+
+  /// How the installed packages of `other` differ from the ones of `self`, sorted by package name.
+  pub fn diff(&self, other: &SwitchState) -> SwitchDiff {
+    let mut diff = SwitchDiff::default();
+    for package in &self.installed {
+      match other.installed_version(&package.name) {
+        None => diff.removed.push(package.clone()),
+        Some(version) if *version != package.version => {
+          diff.changed.push((package.clone(), version.clone()))
+        }
+        Some(_) => (),
+      }
+    }
+    for package in &other.installed {
+      if self.installed_version(&package.name).is_none() {
+        diff.added.push(package.clone());
+      }
+    }
+    diff.added.sort();
+    diff.removed.sort();
+    diff.changed.sort();
+    diff
+  }
+
+  fn set_field(&mut self, name: &str, value: &Value) -> Result<(), PackageError> {
+    match name {
+      "opam-version" => self.opam_version = Some(string(name, value)?),
+      "compiler" => self.compiler = packages(name, value)?,
+      "roots" => self.roots = packages(name, value)?,
+      "installed" => self.installed = packages(name, value)?,
+      "pinned" => self.pinned = packages(name, value)?,
+      _ => self.extra_fields.push((name.to_string(), value.clone())),
+    }
+    Ok(())
+  }
+
+  fn fields(&self) -> Fields {
+    let mut fields = Fields::default();
+    fields.string("opam-version", &self.opam_version);
+    let packages = |packages: &[PackageVersion]| {
+      packages
+        .iter()
+        .map(|package| make_value(ValueKind::String(package.to_string())))
+        .collect()
+    };
+    fields.list("compiler", packages(&self.compiler));
+    fields.list("roots", packages(&self.roots));
+    fields.list("installed", packages(&self.installed));
+    fields.list("pinned", packages(&self.pinned));
+    fields.extra(&self.extra_fields);
+    fields
+  }
+}
+
+impl SwitchExport {
+  pub fn from_opam_file(file: &OpamFile) -> Result<Self, PackageError> {
+    let mut export = SwitchExport::default();
+    for (_, name, value) in fields(file)? {
+      export.state.set_field(name, value)?;
+    }
+    for item in &file.file_contents {
+      if let OpamFileItem::Section(pos, section) = item {
+        match (section.section_kind.as_str(), &section.section_name) {
+          ("package", Some(name)) => {
+            if export.packages.contains_key(name) {
+              return Err(PackageError::DuplicateField(name.clone(), *pos));
+            }
+            let opam = OpamFile {
+              file_contents: section.section_item.clone(),
+            };
+            export.packages.insert(name.clone(), opam);
+          }
+          (kind, _) => return Err(PackageError::UnknownSection(kind.to_string(), *pos)),
+        }
+      }
+    }
+    Ok(export)
+  }
+
+  /// The switch state, followed by a `package` section per package.
+  pub fn to_opam_file(&self) -> OpamFile {
+    let mut file_contents = self.state.fields().0;
+    for (name, opam) in &self.packages {
+      file_contents.push(OpamFileItem::Section(
+        (0, 0),
+        OpamFileSection {
+          section_kind: "package".to_string(),
+          section_name: Some(name.clone()),
+          section_item: opam.file_contents.clone(),
+        },
+      ));
+    }
+    OpamFile { file_contents }
+  }
+}
+
+/// A list of `"name.version"`, or a single one.
+fn packages(field: &str, value: &Value) -> Result<Vec<PackageVersion>, PackageError> {
+  let package = |v: &Value| match &v.kind {
+    ValueKind::String(str) => str.parse::<PackageVersion>().map_err(|err| match err {
+      VersionError::Empty => invalid_type(field, "a list of packages as `name.version`", v),
+      err => PackageError::InvalidVersion(err, v.pos),
+    }),
+    _ => Err(invalid_type(
+      field,
+      "a list of packages as `name.version`",
+      v,
+    )),
+  };
+  match &value.kind {
+    ValueKind::List(lst) => lst.iter().map(package).collect(),
+    _ => Ok(vec![package(value)?]),
+  }
+}
@@ -50,3 +50,2436 @@ fn check_parse() {
   };
   assert_eq!(crate::parse(opam_str).unwrap(), opam_data);
 }
+
+#[test]
+fn check_triple_quoted_string() {
+  use crate::value::*;
+  let opam = crate::parse(r#"synopsis: """a "quoted" word""""#).unwrap();
+  match opam.file_contents.as_slice() {
+    [OpamFileItem::Variable(_, _, value)] => {
+      assert_eq!(
+        value.kind,
+        ValueKind::String("a \"quoted\" word".to_string())
+      );
+      assert_eq!(value.pos, (10, 31));
+    }
+    items => panic!("unexpected items: {:?}", items),
+  }
+}
+
+#[test]
+fn check_section_item_order() {
+  use crate::value::*;
+  let opam = crate::parse("url {\n  src: \"a\"\n  checksum: \"b\"\n}").unwrap();
+  match opam.file_contents.as_slice() {
+    [OpamFileItem::Section(_, section)] => {
+      let names: Vec<&str> = section
+        .section_item
+        .iter()
+        .map(|item| match item {
+          OpamFileItem::Variable(_, name, _) => name.as_str(),
+          OpamFileItem::Section(_, section) => section.section_kind.as_str(),
+        })
+        .collect();
+      assert_eq!(names, ["src", "checksum"]);
+    }
+    items => panic!("unexpected items: {:?}", items),
+  }
+}
+
+#[test]
+fn check_block_comment() {
+  use crate::value::*;
+  let opam =
+    crate::parse("(* a (* nested *) comment *)\nname: \"foo\" (*) still a comment *)").unwrap();
+  match opam.file_contents.as_slice() {
+    [OpamFileItem::Variable(_, name, value)] => {
+      assert_eq!(name, "name");
+      assert_eq!(value.kind, ValueKind::String("foo".to_string()));
+    }
+    items => panic!("unexpected items: {:?}", items),
+  }
+}
+
+#[test]
+fn check_parse_error() {
+  let opam_str = "opam-version: \"2.0\"\nname: }\n";
+  let err = match crate::parse(opam_str) {
+    Err(crate::OpamFileError::Parse(err)) => err,
+    other => panic!("unexpected result: {:?}", other),
+  };
+  assert_eq!(err.token, Some("}".to_string()));
+  assert_eq!(err.pos, (26, 27));
+  assert_eq!((err.line, err.column), (2, 7));
+  assert_eq!(err.source_line, "name: }");
+  assert!(err.expected.contains(&"string".to_string()));
+  assert!(err.expected.contains(&"`[`".to_string()));
+  let message = err.to_string();
+  assert!(message.starts_with("unexpected `}` at line 2, column 7, expected one of "));
+  assert!(message.ends_with("\n  |\n2 | name: }\n  |       ^"));
+}
+
+#[test]
+fn check_parse_error_eof() {
+  let opam_str = "depends: [\n  \"foo\"";
+  let err = match crate::parse(opam_str) {
+    Err(crate::OpamFileError::Parse(err)) => err,
+    other => panic!("unexpected result: {:?}", other),
+  };
+  assert_eq!(err.token, None);
+  assert_eq!((err.line, err.column), (2, 8));
+  assert!(err.expected.contains(&"`]`".to_string()));
+  assert!(err
+    .to_string()
+    .starts_with("unexpected end of input at line 2"));
+}
+
+#[cfg(test)]
+fn get_variable<'a>(opam: &'a crate::value::OpamFile, name: &str) -> &'a crate::value::Value {
+  use crate::value::*;
+  opam
+    .file_contents
+    .iter()
+    .find_map(|item| match item {
+      OpamFileItem::Variable(_, n, value) if n == name => Some(value),
+      _ => None,
+    })
+    .unwrap()
+}
+
+#[test]
+fn check_parse_relop() {
+  use crate::value::*;
+  let opam_str = r#"available: os != "win32""#;
+  let opam_data = OpamFile {
+    file_contents: vec![OpamFileItem::Variable(
+      (0, 24),
+      "available".to_string(),
+      Value {
+        kind: ValueKind::RelOp(
+          RelOp {
+            kind: RelOpKind::Neq,
+            pos: (14, 16),
+          },
+          Box::new(Value {
+            kind: ValueKind::Ident("os".to_string()),
+            pos: (11, 13),
+          }),
+          Box::new(Value {
+            kind: ValueKind::String("win32".to_string()),
+            pos: (17, 24),
+          }),
+        ),
+        pos: (11, 24),
+      },
+    )],
+  };
+  assert_eq!(crate::parse(opam_str).unwrap(), opam_data);
+}
+
+#[test]
+fn check_parse_relop_in_logop() {
+  use crate::value::*;
+  let opam_str = r#"available: arch != "x86_32" & ocaml:version >= "4.10""#;
+  let opam = crate::parse(opam_str).unwrap();
+  match &get_variable(&opam, "available").kind {
+    ValueKind::LogOp(
+      LogOp {
+        kind: LogOpKind::And,
+        ..
+      },
+      l,
+      r,
+    ) => {
+      match &l.kind {
+        ValueKind::RelOp(op, l, r) => {
+          assert_eq!(op.kind, RelOpKind::Neq);
+          assert_eq!(l.kind, ValueKind::Ident("arch".to_string()));
+          assert_eq!(r.kind, ValueKind::String("x86_32".to_string()));
+        }
+        kind => panic!("unexpected value: {:?}", kind),
+      }
+      match &r.kind {
+        ValueKind::RelOp(op, l, r) => {
+          assert_eq!(op.kind, RelOpKind::Geq);
+          assert_eq!(l.kind, ValueKind::Ident("ocaml:version".to_string()));
+          assert_eq!(r.kind, ValueKind::String("4.10".to_string()));
+        }
+        kind => panic!("unexpected value: {:?}", kind),
+      }
+    }
+    kind => panic!("unexpected value: {:?}", kind),
+  }
+}
+
+#[test]
+fn check_parse_relop_in_option() {
+  use crate::value::*;
+  let opam_str = r#"
+    build: [
+      ["./configure" "--prefix=%{prefix}%"] {os != "win32"}
+      ["dune" "build" "-p" name "-j" jobs "@runtest" {with-test & os = "linux"}]
+    ]
+    depexts: [
+      ["libgmp-dev"] {os-family = "debian"}
+      ["gmp"] {os = "macos" & os-distribution = "homebrew"}
+    ]
+    x-ci-accept-failures: [ "centos-7" "oraclelinux-7" ]
+  "#;
+  let opam = crate::parse(opam_str).unwrap();
+  let depexts = match &get_variable(&opam, "depexts").kind {
+    ValueKind::List(lst) => lst,
+    kind => panic!("unexpected value: {:?}", kind),
+  };
+  assert_eq!(depexts.len(), 2);
+  match &depexts[1].kind {
+    ValueKind::Option(_, filter) => {
+      assert_eq!(filter.len(), 1);
+      match &filter[0].kind {
+        ValueKind::LogOp(_, l, r) => {
+          assert!(matches!(l.kind, ValueKind::RelOp(_, _, _)));
+          assert!(matches!(r.kind, ValueKind::RelOp(_, _, _)));
+        }
+        kind => panic!("unexpected value: {:?}", kind),
+      }
+    }
+    kind => panic!("unexpected value: {:?}", kind),
+  }
+  let build = match &get_variable(&opam, "build").kind {
+    ValueKind::List(lst) => lst,
+    kind => panic!("unexpected value: {:?}", kind),
+  };
+  assert_eq!(build.len(), 2);
+  match &build[1].kind {
+    ValueKind::List(args) => assert_eq!(args.len(), 7),
+    kind => panic!("unexpected value: {:?}", kind),
+  }
+  match &get_variable(&opam, "x-ci-accept-failures").kind {
+    ValueKind::List(lst) => assert_eq!(lst.len(), 2),
+    kind => panic!("unexpected value: {:?}", kind),
+  }
+}
+
+#[test]
+fn check_parse_relop_in_values() {
+  use crate::value::*;
+  // `a >= b` inside a list is one binary relop, not `a` followed by `>= b`
+  let opam = crate::parse(r#"x: [ "a" >= "b" ] y: [ "a" {>= "1.0"} "b" {build} ]"#).unwrap();
+  match &get_variable(&opam, "x").kind {
+    ValueKind::List(lst) => {
+      assert_eq!(lst.len(), 1);
+      assert!(matches!(lst[0].kind, ValueKind::RelOp(_, _, _)));
+    }
+    kind => panic!("unexpected value: {:?}", kind),
+  }
+  match &get_variable(&opam, "y").kind {
+    ValueKind::List(lst) => assert_eq!(lst.len(), 2),
+    kind => panic!("unexpected value: {:?}", kind),
+  }
+}
+
+#[test]
+fn check_parse_relop_with_pfxop() {
+  use crate::value::*;
+  let opam = crate::parse(r#"available: !(os = "win32") & ?foo:bar & !_:doc"#).unwrap();
+  match &get_variable(&opam, "available").kind {
+    ValueKind::LogOp(_, l, r) => {
+      match &r.kind {
+        ValueKind::PfxOp(op, v) => {
+          assert_eq!(op.kind, PfxOpKind::Not);
+          assert_eq!(v.kind, ValueKind::Ident("_:doc".to_string()));
+        }
+        kind => panic!("unexpected value: {:?}", kind),
+      }
+      match &l.kind {
+        ValueKind::LogOp(_, l, r) => {
+          assert!(matches!(l.kind, ValueKind::PfxOp(_, _)));
+          assert!(matches!(r.kind, ValueKind::PfxOp(_, _)));
+        }
+        kind => panic!("unexpected value: {:?}", kind),
+      }
+    }
+    kind => panic!("unexpected value: {:?}", kind),
+  }
+}
+
+#[test]
+fn check_parse_logop_precedence() {
+  use crate::value::*;
+  let opam = crate::parse("x: a | b & c y: a & b | c z: a & b & c").unwrap();
+  match &get_variable(&opam, "x").kind {
+    ValueKind::LogOp(op, l, r) => {
+      assert_eq!(op.kind, LogOpKind::Or);
+      assert_eq!(l.kind, ValueKind::Ident("a".to_string()));
+      assert!(matches!(&r.kind, ValueKind::LogOp(op, _, _) if op.kind == LogOpKind::And));
+    }
+    kind => panic!("unexpected value: {:?}", kind),
+  }
+  assert_eq!(get_variable(&opam, "x").pos, (3, 12));
+  match &get_variable(&opam, "y").kind {
+    ValueKind::LogOp(op, l, r) => {
+      assert_eq!(op.kind, LogOpKind::Or);
+      assert!(matches!(&l.kind, ValueKind::LogOp(op, _, _) if op.kind == LogOpKind::And));
+      assert_eq!(r.kind, ValueKind::Ident("c".to_string()));
+    }
+    kind => panic!("unexpected value: {:?}", kind),
+  }
+  match &get_variable(&opam, "z").kind {
+    ValueKind::LogOp(op, l, r) => {
+      assert_eq!(op.kind, LogOpKind::And);
+      assert!(matches!(&l.kind, ValueKind::LogOp(op, _, _) if op.kind == LogOpKind::And));
+      assert_eq!(r.kind, ValueKind::Ident("c".to_string()));
+    }
+    kind => panic!("unexpected value: {:?}", kind),
+  }
+}
+
+#[cfg(test)]
+fn make_logop(
+  kind: crate::value::LogOpKind,
+  l: crate::value::Value,
+  r: crate::value::Value,
+) -> crate::value::Value {
+  use crate::value::*;
+  Value {
+    kind: ValueKind::LogOp(LogOp { kind, pos: (0, 0) }, Box::new(l), Box::new(r)),
+    pos: (0, 0),
+  }
+}
+
+#[cfg(test)]
+fn make_ident(name: &str) -> crate::value::Value {
+  use crate::value::*;
+  Value {
+    kind: ValueKind::Ident(name.to_string()),
+    pos: (0, 0),
+  }
+}
+
+#[test]
+fn check_format_logop_parentheses() {
+  use crate::value::*;
+  let format = |value: Value| {
+    format_opam_file(&OpamFile {
+      file_contents: vec![OpamFileItem::Variable((0, 0), "x".to_string(), value)],
+    })
+  };
+  let a_or_b = make_logop(LogOpKind::Or, make_ident("a"), make_ident("b"));
+  let b_and_c = make_logop(LogOpKind::And, make_ident("b"), make_ident("c"));
+  assert_eq!(
+    format(make_logop(LogOpKind::And, a_or_b.clone(), make_ident("c"))),
+    "x : (a | b) & c\n"
+  );
+  assert_eq!(
+    format(make_logop(LogOpKind::Or, make_ident("a"), b_and_c.clone())),
+    "x : a | b & c\n"
+  );
+  assert_eq!(
+    format(make_logop(LogOpKind::Or, a_or_b.clone(), make_ident("c"))),
+    "x : a | b | c\n"
+  );
+  assert_eq!(
+    format(make_logop(LogOpKind::Or, make_ident("c"), a_or_b)),
+    "x : c | (a | b)\n"
+  );
+  assert_eq!(
+    format(Value {
+      kind: ValueKind::PfxOp(
+        PfxOp {
+          kind: PfxOpKind::Not,
+          pos: (0, 0)
+        },
+        Box::new(b_and_c)
+      ),
+      pos: (0, 0),
+    }),
+    "x : ! (b & c)\n"
+  );
+}
+
+#[test]
+fn check_cst_lossless() {
+  let opam_str = r#"# opam file
+opam-version: "2.0"   # trailing comment
+(* block (* nested *) comment *)
+description: """
+  multi-line "description"
+"""
+depends: [
+  "dune" {>= "2.0"} # build system
+  "ocaml"   {>= "4.08" & < "5.0"}
+]
+url {
+  src: "https://example.com/foo.tar.gz"
+  checksum: [ "md5=0123" ]	(* tab before *)
+}
+extra-source "a.patch" { src: "https://example.com/a.patch" }
+"#;
+  let tree = crate::cst::parse(opam_str).unwrap();
+  assert_eq!(tree.to_string(), opam_str);
+  assert_eq!(tree.items.len(), 5);
+  assert_eq!(
+    tree.to_opam_file().unwrap(),
+    crate::parse(opam_str).unwrap()
+  );
+}
+
+#[test]
+fn check_cst_trivia() {
+  use crate::cst::*;
+  let tree = parse("a: b # c\n(* d *)e: f\n").unwrap();
+  match &tree.items[1] {
+    SyntaxItem::Variable { name, .. } => {
+      assert_eq!(name.text, "e");
+      assert_eq!(
+        name.leading_trivia,
+        vec![
+          Trivia {
+            kind: TriviaKind::Whitespace,
+            text: " ".to_string()
+          },
+          Trivia {
+            kind: TriviaKind::LineComment,
+            text: "# c".to_string()
+          },
+          Trivia {
+            kind: TriviaKind::Whitespace,
+            text: "\n".to_string()
+          },
+          Trivia {
+            kind: TriviaKind::BlockComment,
+            text: "(* d *)".to_string()
+          },
+        ]
+      );
+    }
+    item => panic!("unexpected item: {:?}", item),
+  }
+  assert_eq!(
+    tree.trailing_trivia,
+    vec![Trivia {
+      kind: TriviaKind::Whitespace,
+      text: "\n".to_string()
+    }]
+  );
+}
+
+#[test]
+fn check_cst_set_variable() {
+  use crate::value::*;
+  let opam_str =
+    "opam-version: \"2.0\"\n# keep me\nversion:   \"1.0\"   # old\ndepends: [\n  \"a\"\n]\n";
+  let mut tree = crate::cst::parse(opam_str).unwrap();
+  let depends = crate::parse(r#"x: ["a" "b"]"#).unwrap().file_contents[0].clone();
+  let depends = match depends {
+    OpamFileItem::Variable(_, _, value) => value,
+    _ => unreachable!(),
+  };
+  let version = Value {
+    kind: ValueKind::String("1.1".to_string()),
+    pos: (0, 0),
+  };
+  assert!(tree.set_variable("version", &version).unwrap());
+  assert!(tree.set_variable("depends", &depends).unwrap());
+  assert!(!tree.set_variable("name", &version).unwrap());
+  assert_eq!(
+    tree.to_string(),
+    "opam-version: \"2.0\"\n# keep me\nversion:   \"1.1\"   # old\ndepends: [\"a\" \"b\"]\n"
+  );
+}
+
+#[cfg(test)]
+fn make_string(str: &str) -> crate::value::Value {
+  use crate::value::*;
+  Value {
+    kind: ValueKind::String(str.to_string()),
+    pos: (0, 0),
+  }
+}
+
+#[test]
+fn check_edit_set_field() {
+  use crate::edit::Editor;
+  let opam_str = "opam-version: \"2.0\"\n  version:  \"1.0\" # old\n(* end *)\n";
+  let mut editor = Editor::new(opam_str).unwrap();
+  editor.set_field("version", &make_string("1.1")).unwrap();
+  assert_eq!(
+    editor.source(),
+    "opam-version: \"2.0\"\n  version:  \"1.1\" # old\n(* end *)\n"
+  );
+  editor.set_field("name", &make_string("foo")).unwrap();
+  assert_eq!(
+    editor.source(),
+    "opam-version: \"2.0\"\n  version:  \"1.1\" # old\n  name: \"foo\"\n(* end *)\n"
+  );
+  let mut editor = Editor::new("").unwrap();
+  editor.set_field("name", &make_string("foo")).unwrap();
+  assert_eq!(editor.source(), "name: \"foo\"\n");
+}
+
+#[test]
+fn check_edit_remove_field() {
+  use crate::edit::Editor;
+  let opam_str = "a: \"1\"\n  b: [\n    \"x\"\n  ] # about b\nc: \"3\" d: \"4\"\n";
+  let mut editor = Editor::new(opam_str).unwrap();
+  assert!(editor.remove_field("b").unwrap());
+  assert_eq!(editor.source(), "a: \"1\"\nc: \"3\" d: \"4\"\n");
+  assert!(editor.remove_field("c").unwrap());
+  assert_eq!(editor.source(), "a: \"1\"\nd: \"4\"\n");
+  assert!(!editor.remove_field("c").unwrap());
+}
+
+#[test]
+fn check_edit_insert_list_element() {
+  use crate::edit::Editor;
+  let opam_str = "depends: [\n  \"a\" # first\n  \"c\"\n]\ntags: [\"x\"]\nflags: []\n";
+  let mut editor = Editor::new(opam_str).unwrap();
+  assert!(editor
+    .insert_list_element("depends", 1, &make_string("b"))
+    .unwrap());
+  assert!(editor
+    .insert_list_element("depends", 10, &make_string("d"))
+    .unwrap());
+  assert!(editor
+    .insert_list_element("tags", 0, &make_string("w"))
+    .unwrap());
+  assert!(editor
+    .insert_list_element(
+      "flags",
+      0,
+      &crate::value::Value {
+        kind: crate::value::ValueKind::Ident("light-uninstall".to_string()),
+        pos: (0, 0)
+      }
+    )
+    .unwrap());
+  assert!(!editor
+    .insert_list_element("name", 0, &make_string("x"))
+    .unwrap());
+  assert_eq!(
+    editor.source(),
+    "depends: [\n  \"a\" # first\n  \"b\"\n  \"c\"\n  \"d\"\n]\ntags: [\"w\" \"x\"]\nflags: [light-uninstall]\n"
+  );
+}
+
+#[test]
+fn check_edit_replace_value() {
+  use crate::edit::Editor;
+  use crate::value::*;
+  let mut editor = Editor::new("depends: [\n  \"a\" {>= \"1.0\"}\n]\n").unwrap();
+  let pos = match &editor.file().file_contents[0] {
+    OpamFileItem::Variable(_, _, value) => match &value.kind {
+      ValueKind::List(lst) => match &lst[0].kind {
+        ValueKind::Option(_, filter) => match &filter[0].kind {
+          ValueKind::PrefixRelOp(_, version) => version.pos,
+          _ => unreachable!(),
+        },
+        _ => unreachable!(),
+      },
+      _ => unreachable!(),
+    },
+    _ => unreachable!(),
+  };
+  editor.replace_value(pos, &make_string("2.0")).unwrap();
+  assert_eq!(editor.source(), "depends: [\n  \"a\" {>= \"2.0\"}\n]\n");
+  assert!(editor.replace_value((0, 7), &make_string("x")).is_err());
+  assert_eq!(editor.source(), "depends: [\n  \"a\" {>= \"2.0\"}\n]\n");
+}
+
+#[test]
+fn check_pretty_print() {
+  let opam_str = r#"opam-version: "2.0" synopsis: "A 'quoted' word"
+description: "line 1
+line 2"
+tags: ["a" "b"] flags: []
+depends: ["dune" {>= "3.0"} "ocaml" {>= "4.08" & < "5.0"} "odoc" {with-doc}]
+build: [["dune" "subst"] {dev} ["dune" "build" "-p" name "-j" jobs "@install" "@runtest" {with-test} "@doc" {with-doc}]]
+available: arch != "x86_32" & !(os = "win32" | os = "cygwin")
+url { src: "https://example.com/foo.tar.gz" checksum: ["md5=0123" "sha256=4567"] }
+"#;
+  let expected = r#"opam-version: "2.0"
+synopsis: "A 'quoted' word"
+description: """line 1
+line 2"""
+tags: ["a" "b"]
+flags: []
+depends: [
+  "dune" {>= "3.0"}
+  "ocaml" {>= "4.08" & < "5.0"}
+  "odoc" {with-doc}
+]
+build: [
+  ["dune" "subst"] {dev}
+  [
+    "dune"
+    "build"
+    "-p"
+    name
+    "-j"
+    jobs
+    "@install"
+    "@runtest" {with-test}
+    "@doc" {with-doc}
+  ]
+]
+available: arch != "x86_32" & !(os = "win32" | os = "cygwin")
+url {
+  src: "https://example.com/foo.tar.gz"
+  checksum: ["md5=0123" "sha256=4567"]
+}
+"#;
+  let opam = crate::parse(opam_str).unwrap();
+  let config = crate::pretty::Config::default();
+  assert_eq!(crate::pretty::format_opam_file(&opam, &config), expected);
+}
+
+#[test]
+fn check_pretty_print_package_lists() {
+  let opam = crate::parse(
+    r#"depends: ["ocaml" "dune"] conflicts: ["foo"] depopts: [] tags: ["ocaml" "dune"]"#,
+  )
+  .unwrap();
+  let config = crate::pretty::Config::default();
+  assert_eq!(
+    crate::pretty::format_opam_file(&opam, &config),
+    r#"depends: [
+  "ocaml"
+  "dune"
+]
+conflicts: [
+  "foo"
+]
+depopts: []
+tags: ["ocaml" "dune"]
+"#
+  );
+}
+
+#[test]
+fn check_pretty_print_width() {
+  let opam = crate::parse(r#"tags: ["aaaa" "bbbb" "cccc"]"#).unwrap();
+  let format = |width| crate::pretty::format_opam_file(&opam, &crate::pretty::Config { width });
+  assert_eq!(format(28), "tags: [\"aaaa\" \"bbbb\" \"cccc\"]\n");
+  assert_eq!(
+    format(27),
+    "tags: [\n  \"aaaa\"\n  \"bbbb\"\n  \"cccc\"\n]\n"
+  );
+}
+
+#[cfg(test)]
+mod roundtrip {
+  use crate::value::*;
+  use proptest::prelude::*;
+
+  fn value(kind: ValueKind) -> Value {
+    Value { kind, pos: (0, 0) }
+  }
+
+  fn boxed(kind: ValueKind) -> Box<Value> {
+    Box::new(value(kind))
+  }
+
+  /// Wrap `v` in a group if the grammar would not read it back in a position
+  /// requiring at least `min`, as a file written by hand would have to.
+  fn at_least(v: Value, min: Precedence) -> Box<Value> {
+    if precedence(&v) < min {
+      boxed(ValueKind::Group(vec![v]))
+    } else {
+      Box::new(v)
+    }
+  }
+
+  fn elements(lst: Vec<Value>) -> Vec<Value> {
+    lst
+      .into_iter()
+      .enumerate()
+      .map(|(i, v)| {
+        if i > 0 && starts_with_relop(&v) {
+          value(ValueKind::Group(vec![v]))
+        } else {
+          v
+        }
+      })
+      .collect()
+  }
+
+  fn ident() -> impl Strategy<Value = String> {
+    "[a-z_][a-z0-9_-]{0,6}(\\+[a-z][a-z0-9]{0,3})?(:[a-z][a-z0-9_-]{0,6})?".prop_filter(
+      "not an ident",
+      |s| {
+        crate::lexer::lex(s)
+          .map(|tokens| {
+            tokens.len() == 2 && matches!(tokens[0].0, crate::lexer::TokenKind::IDENT(_))
+          })
+          .unwrap_or(false)
+      },
+    )
+  }
+
+  fn field_name() -> impl Strategy<Value = String> {
+    "[a-z][a-z0-9_-]{0,8}".prop_filter("keyword", |s| s != "true" && s != "false")
+  }
+
+  fn atom() -> impl Strategy<Value = Value> {
+    prop_oneof![
+      any::<bool>().prop_map(|b| value(ValueKind::Bool(b))),
+      any::<isize>().prop_map(|i| value(ValueKind::Int(i))),
+      any::<String>().prop_map(|s| value(ValueKind::String(s))),
+      "[\"\\\\\n a-z]{0,8}".prop_map(|s| value(ValueKind::String(s))),
+      ident().prop_map(|s| value(ValueKind::Ident(s))),
+    ]
+  }
+
+  fn relop() -> impl Strategy<Value = RelOp> {
+    prop_oneof![
+      Just(RelOpKind::Eq),
+      Just(RelOpKind::Neq),
+      Just(RelOpKind::Geq),
+      Just(RelOpKind::Gt),
+      Just(RelOpKind::Leq),
+      Just(RelOpKind::Lt),
+      Just(RelOpKind::Sem),
+    ]
+    .prop_map(|kind| RelOp { kind, pos: (0, 0) })
+  }
+
+  fn any_value() -> impl Strategy<Value = Value> {
+    atom().prop_recursive(4, 32, 4, |inner| {
+      let logop = prop_oneof![Just(LogOpKind::And), Just(LogOpKind::Or)]
+        .prop_map(|kind| LogOp { kind, pos: (0, 0) });
+      let pfxop = prop_oneof![Just(PfxOpKind::Not), Just(PfxOpKind::Defined)]
+        .prop_map(|kind| PfxOp { kind, pos: (0, 0) });
+      let envop = prop_oneof![
+        Just(EnvUpdateOpKind::PlusEq),
+        Just(EnvUpdateOpKind::EqPlus),
+        Just(EnvUpdateOpKind::ColonEq),
+        Just(EnvUpdateOpKind::EqColon),
+        Just(EnvUpdateOpKind::EqPlusEq),
+      ]
+      .prop_map(|kind| EnvUpdateOp { kind, pos: (0, 0) });
+      prop_oneof![
+        prop::collection::vec(inner.clone(), 0..4)
+          .prop_map(|lst| value(ValueKind::List(elements(lst)))),
+        prop::collection::vec(inner.clone(), 0..4)
+          .prop_map(|lst| value(ValueKind::Group(elements(lst)))),
+        (inner.clone(), prop::collection::vec(inner.clone(), 0..3)).prop_map(|(v, lst)| value(
+          ValueKind::Option(at_least(v, Precedence::Option), elements(lst))
+        )),
+        (atom(), relop(), atom()).prop_map(|(l, op, r)| value(ValueKind::RelOp(
+          op,
+          Box::new(l),
+          Box::new(r)
+        ))),
+        (relop(), atom()).prop_map(|(op, v)| value(ValueKind::PrefixRelOp(op, Box::new(v)))),
+        (inner.clone(), logop, inner.clone()).prop_map(|(l, op, r)| {
+          let (l_prec, r_prec) = match op.kind {
+            LogOpKind::Or => (Precedence::Or, Precedence::And),
+            LogOpKind::And => (Precedence::And, Precedence::Prefix),
+          };
+          value(ValueKind::LogOp(
+            op,
+            at_least(l, l_prec),
+            at_least(r, r_prec),
+          ))
+        }),
+        (pfxop, inner)
+          .prop_map(|(op, v)| value(ValueKind::PfxOp(op, at_least(v, Precedence::Prefix)))),
+        (atom(), envop, atom()).prop_map(|(l, op, r)| value(ValueKind::EnvBinding(
+          Box::new(l),
+          op,
+          Box::new(r)
+        ))),
+      ]
+    })
+  }
+
+  fn item() -> impl Strategy<Value = OpamFileItem> {
+    let variable =
+      (field_name(), any_value()).prop_map(|(name, v)| OpamFileItem::Variable((0, 0), name, v));
+    variable.prop_recursive(2, 8, 3, |inner| {
+      (
+        field_name(),
+        prop::option::of(any::<String>()),
+        prop::collection::vec(inner, 0..3),
+      )
+        .prop_map(|(section_kind, section_name, section_item)| {
+          OpamFileItem::Section(
+            (0, 0),
+            OpamFileSection {
+              section_kind,
+              section_name,
+              section_item,
+            },
+          )
+        })
+    })
+  }
+
+  fn opam_file() -> impl Strategy<Value = OpamFile> {
+    prop::collection::vec(item(), 0..5).prop_map(|file_contents| OpamFile { file_contents })
+  }
+
+  fn parse_stripped(input: &str) -> OpamFile {
+    let mut file = crate::parse(input).unwrap_or_else(|err| panic!("{}\n{}", err, input));
+    file.strip_positions();
+    file
+  }
+
+  proptest! {
+    #[test]
+    fn check_format_opam_file_roundtrip(file in opam_file()) {
+      prop_assert_eq!(parse_stripped(&format_opam_file(&file)), file);
+    }
+
+    #[test]
+    fn check_pretty_format_opam_file_roundtrip(file in opam_file(), width in 0usize..100) {
+      let config = crate::pretty::Config { width };
+      prop_assert_eq!(parse_stripped(&crate::pretty::format_opam_file(&file, &config)), file);
+    }
+  }
+
+  #[test]
+  fn check_format_opam_file_escape() {
+    let opam_str = r#"synopsis: "a \"quoted\" \\ word" description: """
+  with "quotes" and ""double"" quotes"""
+url "name" { src: "x" }
+depends: ["a" {>= "1.0"} "b" {build & < "2.0"}] neg: -3 pos: 42
+"#;
+    let file = parse_stripped(opam_str);
+    assert_eq!(parse_stripped(&format_opam_file(&file)), file);
+  }
+}
+
+#[test]
+fn check_eq_ignoring_positions() {
+  let a = crate::parse("depends: [\"a\" {>= \"1.0\" & build}]\nurl { src: \"x\" }").unwrap();
+  let b = crate::parse(
+    "# moved\n  depends: [\n    \"a\" { >= \"1.0\" & build }\n  ]\nurl {\n  src: \"x\"\n}\n",
+  )
+  .unwrap();
+  let c = crate::parse("depends: [\"a\" {>= \"1.0\" | build}]\nurl { src: \"x\" }").unwrap();
+  assert_ne!(a, b);
+  assert!(a.eq_ignoring_positions(&b));
+  assert!(!a.eq_ignoring_positions(&c));
+  let mut stripped = b.clone();
+  stripped.strip_positions();
+  assert!(stripped.eq_ignoring_positions(&a));
+  assert_eq!(
+    stripped.file_contents[1],
+    crate::value::OpamFileItem::Section(
+      (0, 0),
+      crate::value::OpamFileSection {
+        section_kind: "url".to_string(),
+        section_name: None,
+        section_item: vec![crate::value::OpamFileItem::Variable(
+          (0, 0),
+          "src".to_string(),
+          make_string("x")
+        )],
+      }
+    )
+  );
+}
+
+#[test]
+fn check_source_map() {
+  use crate::source_map::*;
+  use crate::value::*;
+  let opam_str = "synopsis: \"caf\u{e9} \u{1f42b}\"\r\ntags: [\"\u{1f42b}\" \"x\"]\n";
+  let (opam, map) = crate::parse_with_source_map(opam_str).unwrap();
+  let tags = match &get_variable(&opam, "tags").kind {
+    ValueKind::List(lst) => lst.clone(),
+    kind => panic!("unexpected value: {:?}", kind),
+  };
+  let line_col = |line, column| Some(LineCol { line, column });
+  assert_eq!(tags[1].pos, (31, 34));
+  assert_eq!(map.byte_range(tags[1].pos), Some(38..41));
+  assert_eq!(&opam_str[map.byte_range(tags[1].pos).unwrap()], "\"x\"");
+  assert_eq!(map.utf16_offset(31), Some(33));
+  assert_eq!(map.line_col(31), line_col(1, 11));
+  assert_eq!(map.line_col_utf16(31), line_col(1, 12));
+  assert_eq!(
+    map.pos_of_line_col_utf16(LineCol {
+      line: 1,
+      column: 12
+    }),
+    Some(31)
+  );
+  assert_eq!(
+    map.pos_of_line_col_utf16(LineCol {
+      line: 0,
+      column: 100
+    }),
+    Some(19)
+  );
+  assert_eq!(map.pos_of_byte_offset(38), Some(31));
+  assert_eq!(map.pos_of_byte_offset(15), None);
+  assert_eq!(map.line_col(map.len()), line_col(2, 0));
+  assert_eq!(map.line_col(map.len() + 1), None);
+}
+
+#[test]
+fn check_interpolation() {
+  use crate::interpolation::*;
+  let var = |packages: &[&str], name: &str, conditional: Option<(&str, &str)>, pos| {
+    Segment::Variable(VariableRef {
+      packages: packages.iter().map(|s| s.to_string()).collect(),
+      name: name.to_string(),
+      conditional: conditional.map(|(t, e)| (t.to_string(), e.to_string())),
+      pos,
+    })
+  };
+  let literal = |s: &str| Segment::Literal(s.to_string());
+  assert_eq!(
+    parse("%{prefix}%/lib/%{name}%"),
+    Ok(vec![
+      var(&[], "prefix", None, (0, 10)),
+      literal("/lib/"),
+      var(&[], "name", None, (15, 23))
+    ])
+  );
+  assert_eq!(
+    parse("--%{ocaml:version}%-%{lablgtk+conf-gtk:installed}%"),
+    Ok(vec![
+      literal("--"),
+      var(&["ocaml"], "version", None, (2, 19)),
+      literal("-"),
+      var(&["lablgtk", "conf-gtk"], "installed", None, (20, 50))
+    ])
+  );
+  assert_eq!(
+    parse("--%{_:with-test?enable:disable}%-tests"),
+    Ok(vec![
+      literal("--"),
+      var(&["_"], "with-test", Some(("enable", "disable")), (2, 32)),
+      literal("-tests")
+    ])
+  );
+  assert_eq!(
+    parse("100%% %{x?:}%"),
+    Ok(vec![
+      literal("100% "),
+      var(&[], "x", Some(("", "")), (6, 13))
+    ])
+  );
+  assert_eq!(parse(""), Ok(vec![]));
+  assert_eq!(parse("%% %"), Ok(vec![literal("% %")]));
+  assert_eq!(
+    parse("a%{prefix"),
+    Err(InterpolationError::Unclosed((1, 9)))
+  );
+  assert_eq!(
+    parse("a %{pkg:}% b"),
+    Err(InterpolationError::InvalidVariable(
+      "pkg:".to_string(),
+      (2, 10)
+    ))
+  );
+  assert_eq!(
+    parse("%{ name }%"),
+    Err(InterpolationError::InvalidVariable(
+      " name ".to_string(),
+      (0, 10)
+    ))
+  );
+  assert_eq!(
+    parse("%{x?yes}%"),
+    Err(InterpolationError::InvalidVariable(
+      "x?yes".to_string(),
+      (0, 9)
+    ))
+  );
+  let str = "100%% %{_:doc?--with-doc:}%/%{a+b:c}%";
+  let segments = parse(str).unwrap();
+  assert_eq!(
+    segments.iter().map(|s| s.to_string()).collect::<String>(),
+    str
+  );
+}
+
+#[test]
+fn check_filter_eval() {
+  use crate::filter::*;
+  let resolver = |scope: Scope, name: &str| match (scope, name) {
+    (Scope::Global, "os") => Some(VariableValue::String("linux".to_string())),
+    (Scope::Global, "build") => Some(VariableValue::Bool(true)),
+    (Scope::Global, "with-test") => Some(VariableValue::String("false".to_string())),
+    (Scope::Current, "dev") => Some(VariableValue::Bool(false)),
+    (Scope::Package("ocaml"), "version") => Some(VariableValue::String("4.10.0".to_string())),
+    (Scope::Package(_), "installed") => Some(VariableValue::Bool(true)),
+    (Scope::Package("lwt"), "enable") => Some(VariableValue::String("enable".to_string())),
+    (Scope::Global, "jobs") => Some(VariableValue::String("4".to_string())),
+    _ => None,
+  };
+  let eval_str = |str: &str| {
+    let opam = crate::parse(&format!("f: {}", str)).unwrap();
+    eval(get_variable(&opam, "f"), &resolver)
+  };
+  let bool = |b| Ok(FilterValue::Bool(b));
+  assert_eq!(eval_str("build & os = \"linux\""), bool(true));
+  assert_eq!(eval_str("os != \"linux\" | with-test"), bool(false));
+  assert_eq!(eval_str("ocaml:version >= \"4.08\""), bool(true));
+  assert_eq!(eval_str("ocaml:version < \"4.10.0~beta1\""), bool(false));
+  assert_eq!(eval_str("!_:dev"), bool(true));
+  assert_eq!(eval_str("foo+bar:installed"), bool(true));
+  assert_eq!(eval_str("foo+_:installed"), Ok(FilterValue::Undefined));
+  assert_eq!(eval_str("_:dev & foo:installed"), bool(false));
+  assert_eq!(eval_str("os"), Ok(FilterValue::String("linux".to_string())));
+  assert_eq!(eval_str("arch = \"x86_32\""), Ok(FilterValue::Undefined));
+  assert_eq!(eval_str("!arch"), Ok(FilterValue::Undefined));
+  assert_eq!(eval_str("arch | build"), bool(true));
+  assert_eq!(eval_str("arch & _:dev"), bool(false));
+  assert_eq!(eval_str("arch & build"), Ok(FilterValue::Undefined));
+  assert_eq!(eval_str("?arch"), bool(false));
+  assert_eq!(eval_str("?os & (build = true)"), bool(true));
+  assert_eq!(eval_str("jobs > 1"), bool(true));
+  assert_eq!(eval_str("jobs >= 10"), bool(false));
+  assert_eq!(eval_str("arch = 1"), Ok(FilterValue::Undefined));
+  assert_eq!(
+    eval_str("build & os"),
+    Err(EvalError::NotABool("os".to_string(), (11, 13)))
+  );
+  assert_eq!(
+    eval_str("foo+lwt:enable"),
+    Err(EvalError::NotABool("enable".to_string(), (3, 17)))
+  );
+  assert_eq!(
+    eval_str("[build]"),
+    Err(EvalError::NotAFilter("[build]".to_string(), (3, 10)))
+  );
+  let opam = crate::parse("f: \"foo\" {>= \"1.0\"}").unwrap();
+  match &get_variable(&opam, "f").kind {
+    crate::value::ValueKind::Option(_, filters) => assert_eq!(
+      eval_to_bool(&filters[0], &resolver),
+      Err(EvalError::NotAFilter(">= \"1.0\"".to_string(), (10, 18)))
+    ),
+    kind => panic!("unexpected value: {:?}", kind),
+  }
+}
+
+#[test]
+fn check_interpolation_expand() {
+  use crate::filter::*;
+  use crate::interpolation::*;
+  let resolver = |scope: Scope, name: &str| match (scope, name) {
+    (Scope::Global, "prefix") => Some(VariableValue::String("/usr/local".to_string())),
+    (Scope::Global, "jobs") => Some(VariableValue::String("4".to_string())),
+    (Scope::Current, "name") => Some(VariableValue::String("foo".to_string())),
+    (Scope::Package("ocaml"), "native") => Some(VariableValue::Bool(true)),
+    (Scope::Package("lablgtk"), "enable") => Some(VariableValue::String("enable".to_string())),
+    (Scope::Package(_), "installed") => Some(VariableValue::String("true".to_string())),
+    (Scope::Package(_), "enable") => Some(VariableValue::Bool(false)),
+    _ => None,
+  };
+  let expand_str = |str| expand(str, &resolver);
+  assert_eq!(
+    expand_str("%{prefix}%/lib/%{_:name}%"),
+    Ok("/usr/local/lib/foo".to_string())
+  );
+  assert_eq!(expand_str("%{ocaml:native}%"), Ok("true".to_string()));
+  assert_eq!(expand_str("%{foo+bar:installed}%"), Ok("true".to_string()));
+  assert_eq!(
+    expand_str("--%{lablgtk:enable}%-gtk"),
+    Ok("--enable-gtk".to_string())
+  );
+  assert_eq!(
+    expand_str("--%{foo:enable}%-gtk"),
+    Ok("--false-gtk".to_string())
+  );
+  assert_eq!(expand_str("%{lablgtk+foo:enable}%"), Ok("".to_string()));
+  assert_eq!(expand_str("[%{undefined}%]"), Ok("[]".to_string()));
+  assert_eq!(
+    expand_str("%{ocaml:native?ocamlopt:ocamlc}%"),
+    Ok("ocamlopt".to_string())
+  );
+  assert_eq!(expand_str("%{undefined?yes:no}%"), Ok("no".to_string()));
+  assert_eq!(expand_str("%{prefix?yes:no}%"), Ok("no".to_string()));
+  assert_eq!(expand_str("100%%"), Ok("100%".to_string()));
+  assert_eq!(
+    expand_str("%{prefix"),
+    Err(InterpolationError::Unclosed((0, 8)))
+  );
+
+  let opam = crate::parse(
+    r#"build: [
+      ["./configure" "--prefix=%{prefix}%" "--%{ocaml:native?enable:disable}%-native"]
+      [make "-j%{jobs}%"] {os = "%{os}%"}
+      [make "%{bad"]
+    ]"#,
+  )
+  .unwrap();
+  let build = get_variable(&opam, "build");
+  let expected = crate::parse(
+    r#"build: [
+      ["./configure" "--prefix=/usr/local" "--enable-native"]
+      [make "-j4"] {os = "%{os}%"}
+      [make "%{bad"]
+    ]"#,
+  )
+  .unwrap();
+  let expected = get_variable(&expected, "build");
+  let lst = |value: &crate::value::Value| match &value.kind {
+    crate::value::ValueKind::List(lst) => lst.clone(),
+    kind => panic!("unexpected value: {:?}", kind),
+  };
+  for i in 0..2 {
+    assert!(expand_value(&lst(build)[i], &resolver)
+      .unwrap()
+      .eq_ignoring_positions(&lst(expected)[i]));
+  }
+  assert_eq!(
+    expand_value(build, &resolver),
+    Err((InterpolationError::Unclosed((0, 5)), (150, 157)))
+  );
+  let filter = crate::parse(r#"f: "%{os" = "linux""#).unwrap();
+  assert_eq!(
+    eval(get_variable(&filter, "f"), &resolver),
+    Err(EvalError::Interpolation(
+      InterpolationError::Unclosed((0, 4)),
+      (3, 9)
+    ))
+  );
+}
+
+#[test]
+fn check_filter_simplify() {
+  use crate::filter::*;
+  let resolver = |scope: Scope, name: &str| match (scope, name) {
+    (Scope::Global, "os") => Some(VariableValue::String("linux".to_string())),
+    (Scope::Global, "arch") => Some(VariableValue::String("x86_64".to_string())),
+    (Scope::Global, "build") => Some(VariableValue::Bool(true)),
+    (Scope::Package("ocaml"), "version") => Some(VariableValue::String("4.14.1".to_string())),
+    _ => None,
+  };
+  let simplify_str = |str: &str| {
+    let opam = crate::parse(&format!("f: {}", str)).unwrap();
+    simplify(get_variable(&opam, "f"), &resolver).map(|v| crate::value::value_to_string(&v))
+  };
+  assert_eq!(
+    simplify_str("os = \"linux\" & with-test"),
+    Ok("with-test".to_string())
+  );
+  assert_eq!(
+    simplify_str("os = \"macos\" & with-test"),
+    Ok("false".to_string())
+  );
+  assert_eq!(
+    simplify_str("arch = \"arm64\" | !with-doc"),
+    Ok("! with-doc".to_string())
+  );
+  assert_eq!(
+    simplify_str("build | with-test & with-doc"),
+    Ok("true".to_string())
+  );
+  assert_eq!(
+    simplify_str("(with-test | dev) & ocaml:version >= \"4.08\""),
+    Ok("with-test | dev".to_string())
+  );
+  assert_eq!(
+    simplify_str("with-test & (with-doc | os != \"linux\")"),
+    Ok("with-test & with-doc".to_string())
+  );
+  assert_eq!(
+    simplify_str("os-distribution = \"%{os}%\" & ?os"),
+    Ok("os-distribution = \"linux\"".to_string())
+  );
+  assert_eq!(
+    simplify_str("\"%{os-family}%\" = os"),
+    Ok("\"%{os-family}%\" = \"linux\"".to_string())
+  );
+  assert_eq!(
+    simplify_str("ocaml:version < \"5.0\""),
+    Ok("true".to_string())
+  );
+  assert_eq!(simplify_str("arch"), Ok("\"x86_64\"".to_string()));
+  assert_eq!(simplify_str("jobs > 1"), Ok("jobs > 1".to_string()));
+  assert_eq!(
+    simplify_str("!(with-test | dev) & build"),
+    Ok("! (with-test | dev)".to_string())
+  );
+  assert_eq!(simplify_str("?with-test"), Ok("? with-test".to_string()));
+  assert_eq!(
+    simplify_str("os & with-test"),
+    Err(EvalError::NotABool("os".to_string(), (3, 5)))
+  );
+}
+
+#[test]
+fn check_version_compare() {
+  use crate::version::*;
+  use std::cmp::Ordering::{self, *};
+  // the cases of opam's version comparison tests, and of the Debian ordering it follows
+  let table: &[(&str, &str, Ordering)] = &[
+    ("1.0", "1.0", Equal),
+    ("1.0", "1.1", Less),
+    ("1.10", "1.9", Greater),
+    ("1.01", "1.1", Equal),
+    ("01", "1", Equal),
+    ("1.0.0", "1.0", Greater),
+    ("1.0", "1.0.", Less),
+    ("1.0a", "1.0", Greater),
+    ("1.0a", "1.0.", Less),
+    ("1.0a", "1.0A", Greater),
+    ("1.0+", "1.0-", Less),
+    ("1_1", "1.1", Greater),
+    ("1.0~", "1.0", Less),
+    ("1.0~beta", "1.0", Less),
+    ("1.0~beta", "1.0~", Greater),
+    ("1.0~~", "1.0~", Less),
+    ("1.0~~a", "1.0~~", Greater),
+    ("1.0~alpha", "1.0~beta", Less),
+    ("1.2.3~rc1", "1.2.3~rc2", Less),
+    ("1.2.3~rc10", "1.2.3~rc9", Greater),
+    ("5.0.0~beta1", "4.14.1", Greater),
+    ("4.14.1", "4.14.1+flambda", Less),
+    ("4.14.1+flambda", "4.14.2", Less),
+    ("v0.16.0", "v0.15.1", Greater),
+    ("v0.16.0", "0.16.0", Greater),
+    ("0.9.9", "1.0.0", Less),
+    ("20210101", "20201231", Greater),
+    ("99999999999999999999", "100000000000000000000", Less),
+    ("a", "b", Less),
+    ("a", "a0", Equal),
+    ("a~", "a", Less),
+    ("dev", "1.0", Greater),
+    ("~", "", Less),
+    ("", "", Equal),
+  ];
+  for (a, b, ord) in table {
+    assert_eq!(compare(a, b), *ord, "compare {:?} {:?}", a, b);
+    assert_eq!(compare(b, a), ord.reverse(), "compare {:?} {:?}", b, a);
+    if let (Ok(va), Ok(vb)) = (a.parse::<OpamVersion>(), b.parse::<OpamVersion>()) {
+      assert_eq!(va.cmp(&vb), *ord);
+      assert_eq!(va == vb, *ord == Equal);
+    }
+  }
+  let hash = |v: &OpamVersion| {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    v.hash(&mut hasher);
+    hasher.finish()
+  };
+  let v1: OpamVersion = "1.01~beta".parse().unwrap();
+  let v2: OpamVersion = "1.1~beta".parse().unwrap();
+  assert_eq!(v1, v2);
+  assert_eq!(hash(&v1), hash(&v2));
+  assert_eq!(v1.to_string(), "1.01~beta");
+  assert_eq!("".parse::<OpamVersion>(), Err(VersionError::Empty));
+  assert_eq!(
+    "1.0 beta".parse::<OpamVersion>(),
+    Err(VersionError::InvalidChar(' ', "1.0 beta".to_string()))
+  );
+}
+
+#[test]
+fn check_version_formula() {
+  use crate::formula::*;
+  use crate::value::*;
+  use crate::version::OpamVersion;
+  let formula = |str: &str| {
+    let opam = crate::parse(&format!("f: \"foo\" {{{}}}", str)).unwrap();
+    match &get_variable(&opam, "f").kind {
+      ValueKind::Option(_, lst) => VersionFormula::from_values(lst),
+      kind => panic!("unexpected value: {:?}", kind),
+    }
+  };
+  let version = |str: &str| str.parse::<OpamVersion>().unwrap();
+  let atom = |kind, str: &str| VersionFormula::Atom(kind, version(str));
+  let f = formula(">= \"1.2\" & < \"2.0\"").unwrap();
+  assert_eq!(
+    f,
+    VersionFormula::And(
+      Box::new(atom(RelOpKind::Geq, "1.2")),
+      Box::new(atom(RelOpKind::Lt, "2.0"))
+    )
+  );
+  assert!(f.matches(&version("1.9.3")));
+  assert!(f.matches(&version("1.2")));
+  assert!(!f.matches(&version("1.2~rc1")));
+  assert!(!f.matches(&version("2.0")));
+  let f = formula("(< \"1.0\" | > \"2.0\") & != \"3.0\"").unwrap();
+  assert!(f.matches(&version("0.9")) && f.matches(&version("2.1")));
+  assert!(!f.matches(&version("1.5")) && !f.matches(&version("3.0")));
+  assert_eq!(f.to_string(), "(< \"1.0\" | > \"2.0\") & != \"3.0\"");
+  assert_eq!(formula(&f.to_string()).unwrap(), f);
+  let any = formula("").unwrap();
+  assert_eq!(any, VersionFormula::Any);
+  assert!(any.matches(&version("0")));
+  assert_eq!(any.to_value(), None);
+  assert_eq!(any.intersection(&f), f);
+  assert_eq!(any.union(&f), VersionFormula::Any);
+
+  assert!(!f.is_empty());
+  assert!(formula("> \"2.0\" & < \"1.0\"").unwrap().is_empty());
+  assert!(!formula("> \"1.0\" & < \"1.0.0\"").unwrap().is_empty());
+  assert!(formula("= \"1.0\" & != \"1.00\"").unwrap().is_empty());
+  assert!(formula(">= \"1.0\" & <= \"1.0\" & != \"1.0\"")
+    .unwrap()
+    .is_empty());
+  assert!(formula(">= \"1.0\" & < \"1.0\"").unwrap().is_empty());
+  assert!(!formula(">= \"1.0\" & <= \"1.0\"").unwrap().is_empty());
+  assert!(!formula("(= \"1.0\" | = \"2.0\") & > \"1.5\"")
+    .unwrap()
+    .is_empty());
+  assert!(formula("= \"1.0\" & = \"2.0\"").unwrap().is_empty());
+  let old = formula("< \"1.0\"").unwrap();
+  let new = formula(">= \"2.0\"").unwrap();
+  assert!(old.intersection(&new).is_empty());
+  assert!(!old.union(&new).is_empty());
+  assert_eq!(
+    old.union(&new).intersection(&old).to_string(),
+    "(< \"1.0\" | >= \"2.0\") & < \"1.0\""
+  );
+
+  assert_eq!(
+    formula("build & >= \"1.0\""),
+    Err(FormulaError::NotAVersionFormula(
+      "build".to_string(),
+      (10, 15)
+    ))
+  );
+  assert_eq!(
+    formula("~ \"1.0\""),
+    Err(FormulaError::NotAVersionFormula(
+      "~ \"1.0\"".to_string(),
+      (10, 17)
+    ))
+  );
+  assert_eq!(
+    formula(">= \"1 0\""),
+    Err(FormulaError::InvalidVersion(
+      crate::version::VersionError::InvalidChar(' ', "1 0".to_string()),
+      (13, 18)
+    ))
+  );
+}
+
+#[test]
+fn check_package_formula() {
+  use crate::formula::*;
+  use crate::value::*;
+  let opam_str = r#"depends: [
+    "ocaml" {>= "4.08" & < "5.0"}
+    "dune" {>= "3.0" & build}
+    "ppx_inline_test" {with-test | dev}
+    "odoc" {with-doc & os != "win32"}
+    "foo-base" {= _:version}
+    ("lwt" {>= "5"} | "async" & "async_unix")
+    "lwt"
+  ]"#;
+  let opam = crate::parse(opam_str).unwrap();
+  let depends = get_variable(&opam, "depends");
+  let formula = PackageFormula::from_value(depends).unwrap();
+  assert_eq!(
+    formula.package_names(),
+    [
+      "ocaml",
+      "dune",
+      "ppx_inline_test",
+      "odoc",
+      "foo-base",
+      "lwt",
+      "async",
+      "async_unix"
+    ]
+  );
+  let atoms = formula.atoms();
+  assert_eq!(atoms.len(), 9);
+  assert_eq!(atoms[0].flags, []);
+  assert_eq!(atoms[0].version.to_string(), ">= \"4.08\" & < \"5.0\"");
+  assert_eq!(atoms[1].flags, [DependencyFlag::Build]);
+  assert_eq!(atoms[1].version.to_string(), ">= \"3.0\"");
+  assert_eq!(atoms[2].flags, []);
+  assert_eq!(
+    atoms[2].filter.as_ref().map(value_to_string),
+    Some("with-test | dev".to_string())
+  );
+  assert_eq!(atoms[3].flags, [DependencyFlag::WithDoc]);
+  assert_eq!(
+    atoms[3].filter.as_ref().map(value_to_string),
+    Some("os != \"win32\"".to_string())
+  );
+  assert_eq!(atoms[4].version, VersionFormula::Any);
+  assert_eq!(
+    atoms[4].filter.as_ref().map(value_to_string),
+    Some("= _:version".to_string())
+  );
+  match &formula {
+    PackageFormula::And(lst) => match &lst[5] {
+      PackageFormula::Or(alternatives) => {
+        assert_eq!(alternatives.len(), 2);
+        assert!(matches!(&alternatives[1], PackageFormula::And(lst) if lst.len() == 2));
+      }
+      f => panic!("unexpected formula: {:?}", f),
+    },
+    f => panic!("unexpected formula: {:?}", f),
+  }
+  assert_eq!(
+    value_to_string(&formula.to_value()),
+    r#"["ocaml" {>= "4.08" & < "5.0"} "dune" {build & >= "3.0"} "ppx_inline_test" {with-test | dev} "odoc" {with-doc & os != "win32"} "foo-base" {= _:version} ("lwt" {>= "5"} | "async" & "async_unix") "lwt"]"#
+  );
+  assert_eq!(PackageFormula::from_value(&formula.to_value()), Ok(formula));
+
+  let mut atom = PackageAtom::new("foo");
+  assert_eq!(value_to_string(&atom.to_value()), "\"foo\"");
+  atom.flags.push(DependencyFlag::WithTest);
+  atom.version = VersionFormula::Atom(RelOpKind::Geq, "1.0".parse().unwrap())
+    .union(&VersionFormula::Atom(RelOpKind::Eq, "0.9".parse().unwrap()));
+  assert_eq!(
+    value_to_string(&atom.to_value()),
+    "\"foo\" {with-test & (>= \"1.0\" | = \"0.9\")}"
+  );
+  let empty = crate::parse("depends: []").unwrap();
+  assert_eq!(
+    PackageFormula::from_value(get_variable(&empty, "depends")),
+    Ok(PackageFormula::And(vec![]))
+  );
+  let invalid = crate::parse("depends: [\"foo\" bar]").unwrap();
+  assert_eq!(
+    PackageFormula::from_value(get_variable(&invalid, "depends")),
+    Err(FormulaError::NotAPackageFormula(
+      "bar".to_string(),
+      (16, 19)
+    ))
+  );
+  let invalid = crate::parse("depends: [\"foo\" {>= \"1 0\"}]").unwrap();
+  assert!(matches!(
+    PackageFormula::from_value(get_variable(&invalid, "depends")),
+    Err(FormulaError::InvalidVersion(_, (20, 25)))
+  ));
+}
+
+#[test]
+fn check_package_formula_normal_form() {
+  use crate::filter::*;
+  use crate::formula::*;
+  // from the opam files of cohttp-lwt-unix and ocaml-variants
+  let opam_str = r#"depends: [
+    "ocaml" {>= "4.08"}
+    "dune" {>= "3.0"}
+    "conduit-lwt" {>= "6.0.0"}
+    ("conduit-lwt-unix" {>= "6.0.0"} | "conduit-async")
+    "cmdliner" {>= "1.1.0"}
+    "alcotest" {with-test}
+    "ounit2" {with-test & os != "win32"}
+    "odoc" {with-doc}
+    "ocaml-base-compiler" {= _:version} | "ocaml-variants" | "ocaml-system"
+    ("ppx_sexp_conv" {dev} | "sexplib0")
+  ]"#;
+  let opam = crate::parse(opam_str).unwrap();
+  let formula = PackageFormula::from_value(get_variable(&opam, "depends")).unwrap();
+  let names = |clauses: Vec<Vec<PackageAtom>>| {
+    clauses
+      .iter()
+      .map(|clause| {
+        clause
+          .iter()
+          .map(|a| a.name.as_str())
+          .collect::<Vec<_>>()
+          .join(" | ")
+      })
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(
+    names(formula.to_cnf(100).unwrap()),
+    [
+      "ocaml",
+      "dune",
+      "conduit-lwt",
+      "conduit-lwt-unix | conduit-async",
+      "cmdliner",
+      "alcotest",
+      "ounit2",
+      "odoc",
+      "ocaml-base-compiler | ocaml-variants | ocaml-system",
+      "ppx_sexp_conv | sexplib0"
+    ]
+  );
+  let dnf = formula.to_dnf(100).unwrap();
+  assert_eq!(dnf.len(), 12);
+  assert_eq!(dnf.iter().map(|c| c.len()).max(), Some(10));
+  assert_eq!(formula.to_dnf(11), Err(FormulaError::TooLarge(11)));
+  assert!(formula.to_cnf(9).is_err());
+
+  let resolver = |scope: Scope, name: &str| match (scope, name) {
+    (Scope::Global, "with-test") => Some(VariableValue::Bool(true)),
+    (Scope::Global, "os") => Some(VariableValue::String("win32".to_string())),
+    (Scope::Current, "version") => Some(VariableValue::String("4.14.1".to_string())),
+    _ => None,
+  };
+  let evaluated = formula.eval_conditions(&resolver).unwrap();
+  let cnf = evaluated.to_cnf(100).unwrap();
+  assert_eq!(
+    names(cnf.clone()),
+    [
+      "ocaml",
+      "dune",
+      "conduit-lwt",
+      "conduit-lwt-unix | conduit-async",
+      "cmdliner",
+      "alcotest",
+      "ocaml-base-compiler | ocaml-variants | ocaml-system",
+      "sexplib0"
+    ]
+  );
+  assert_eq!(cnf[6][0].version.to_string(), "= \"4.14.1\"");
+  assert!(cnf
+    .iter()
+    .flatten()
+    .all(|a| a.flags.is_empty() && a.filter.is_none()));
+  assert_eq!(evaluated.to_dnf(100).unwrap().len(), 6);
+
+  let opam =
+    crate::parse(r#"depends: ["a" {with-test | >= "2.0"} "b" {os = "linux" & (dev | = "1.0")}]"#)
+      .unwrap();
+  let formula = PackageFormula::from_value(get_variable(&opam, "depends")).unwrap();
+  let versions = |resolver: &dyn Fn(Scope, &str) -> Option<VariableValue>| {
+    formula
+      .eval_conditions(&resolver)
+      .unwrap()
+      .atoms()
+      .iter()
+      .map(|a| format!("{} {}", a.name, a.version))
+      .collect::<Vec<_>>()
+  };
+  assert_eq!(versions(&resolver), ["a "]);
+  assert_eq!(
+    versions(&|scope: Scope, name: &str| match (scope, name) {
+      (Scope::Global, "os") => Some(VariableValue::String("linux".to_string())),
+      (Scope::Global, "dev") => Some(VariableValue::Bool(true)),
+      _ => None,
+    }),
+    ["a >= \"2.0\"", "b "]
+  );
+
+  // the same atom at two places is kept once
+  let opam = crate::parse(r#"depends: ["a" {os = "linux"} "a" {os = "linux"}]"#).unwrap();
+  let formula = PackageFormula::from_value(get_variable(&opam, "depends")).unwrap();
+  assert_eq!(formula.to_cnf(100).unwrap().len(), 1);
+  assert_eq!(formula.to_dnf(100).unwrap()[0].len(), 1);
+
+  // `("a" | "b") & ("c" | "d")`, twelve times, has 4096 alternatives in DNF
+  let opam = crate::parse(&format!(
+    "depends: [{}]",
+    (0..12)
+      .map(|i| format!("(\"a{0}\" | \"b{0}\")", i))
+      .collect::<Vec<_>>()
+      .join(" ")
+  ))
+  .unwrap();
+  let formula = PackageFormula::from_value(get_variable(&opam, "depends")).unwrap();
+  assert_eq!(formula.to_cnf(100).unwrap().len(), 12);
+  assert_eq!(formula.to_dnf(1000), Err(FormulaError::TooLarge(1000)));
+  assert_eq!(formula.to_dnf(4096).unwrap().len(), 4096);
+
+  let empty = PackageFormula::And(vec![]);
+  assert_eq!(empty.to_cnf(1), Ok(vec![]));
+  assert_eq!(empty.to_dnf(1), Ok(vec![vec![]]));
+}
+
+#[test]
+fn check_opam_package() {
+  use crate::package::*;
+  use crate::value::*;
+  let opam_str = r#"opam-version: "2.0"
+name: "cohttp-lwt-unix"
+version: "6.0.0~alpha2"
+synopsis: "CoHTTP implementation for Unix and Windows using Lwt"
+description: """
+An implementation of an HTTP client and server using the Lwt
+concurrency library."""
+maintainer: ["Anil Madhavapeddy <anil@recoil.org>"]
+authors: ["Anil Madhavapeddy" "Stefano Zacchiroli"]
+license: "ISC"
+tags: ["org:mirage" "org:xapi-project"]
+homepage: "https://github.com/mirage/ocaml-cohttp"
+doc: "https://mirage.github.io/ocaml-cohttp/"
+bug-reports: "https://github.com/mirage/ocaml-cohttp/issues"
+depends: [
+  "dune" {>= "3.0"}
+  "conduit-lwt" {>= "5.0.0"}
+  "ounit2" {with-test}
+]
+conflicts: ["lwt" {< "5.0"}]
+available: os != "win32"
+flags: [avoid-version]
+build: [
+  ["dune" "subst"] {dev}
+  [
+    "dune"
+    "build"
+    "-p"
+    name
+    "-j"
+    jobs
+    "@install"
+    "@runtest" {with-test}
+  ]
+]
+install: ["make" "install"]
+setenv: [LD_LIBRARY_PATH += "%{lib}%/stublibs"]
+depexts: [
+  ["libev-dev"] {os-family = "debian"}
+  ["libev"] {os = "macos" & os-distribution = "homebrew"}
+]
+post-messages: ["Done" {success} "Failed"]
+dev-repo: "git+https://github.com/mirage/ocaml-cohttp.git"
+pin-depends: [["conduit-lwt.dev" "git+https://github.com/mirage/ocaml-conduit.git"]]
+extra-files: ["fix.patch" "md5=0123456789abcdef0123456789abcdef"]
+patches: ["fix.patch" {os = "macos"}]
+x-commit-hash: "d3ea0a7a7ba21e6e89b1cfb0b8d7c9b9c1c0c5a0"
+url {
+  src: "https://github.com/mirage/ocaml-cohttp/releases/download/v6.0.0_alpha2/cohttp-6.0.0.alpha2.tbz"
+  checksum: [
+    "sha256=2f14a6f6a4d8a3d69d1b5b5a8d5f6d6e8d9f0a1b2c3d4e5f6a7b8c9d0e1f2a3b"
+  ]
+}
+extra-source "config.guess" {
+  src: "https://example.com/config.guess"
+  checksum: "md5=0123456789abcdef0123456789abcdef"
+}
+"#;
+  let opam = crate::parse(opam_str).unwrap();
+  let package = OpamPackage::from_opam_file(&opam).unwrap();
+  assert_eq!(package.opam_version.as_deref(), Some("2.0"));
+  assert_eq!(package.name.as_deref(), Some("cohttp-lwt-unix"));
+  assert_eq!(package.version, Some("6.0.0~alpha2".parse().unwrap()));
+  assert_eq!(
+    package.description.as_deref(),
+    Some("\nAn implementation of an HTTP client and server using the Lwt\nconcurrency library.")
+  );
+  assert_eq!(package.authors.len(), 2);
+  assert_eq!(package.license, ["ISC"]);
+  assert_eq!(
+    package.depends.as_ref().unwrap().package_names(),
+    ["dune", "conduit-lwt", "ounit2"]
+  );
+  assert_eq!(package.conflicts.as_ref().unwrap().package_names(), ["lwt"]);
+  assert!(matches!(
+    package.available.as_ref().map(|v| &v.kind),
+    Some(ValueKind::RelOp(_, _, _))
+  ));
+  assert_eq!(package.flags, ["avoid-version"]);
+  assert_eq!(package.build.len(), 2);
+  assert!(package.build[0].filter.is_some());
+  assert_eq!(package.build[1].args.len(), 8);
+  assert_eq!(
+    package.build[1].args[3],
+    Argument {
+      kind: ArgumentKind::Ident("name".to_string()),
+      filter: None
+    }
+  );
+  assert!(package.build[1].args[7].filter.is_some());
+  assert_eq!(package.install.len(), 1);
+  assert_eq!(package.install[0].args.len(), 2);
+  assert_eq!(
+    package.setenv,
+    [EnvUpdate {
+      variable: "LD_LIBRARY_PATH".to_string(),
+      op: EnvUpdateOpKind::PlusEq,
+      value: "%{lib}%/stublibs".to_string()
+    }]
+  );
+  assert_eq!(package.depexts.len(), 2);
+  assert_eq!(package.depexts[1].packages, ["libev"]);
+  assert_eq!(package.post_messages.len(), 2);
+  assert!(package.post_messages[1].filter.is_none());
+  assert_eq!(package.pin_depends[0].0, "conduit-lwt.dev");
+  assert_eq!(package.extra_files[0].0, "fix.patch");
+  assert_eq!(package.patches[0].value, "fix.patch");
+  assert_eq!(package.extra_fields.len(), 1);
+  assert_eq!(package.extra_fields[0].0, "x-commit-hash");
+  let url = package.url.as_ref().unwrap();
+  assert!(url.src.as_ref().unwrap().ends_with(".tbz"));
+  assert_eq!(url.checksum.len(), 1);
+  assert_eq!(package.extra_sources[0].name, "config.guess");
+  assert_eq!(package.extra_sources[0].checksum.len(), 1);
+
+  let error = |str: &str| OpamPackage::from_opam_file(&crate::parse(str).unwrap()).unwrap_err();
+  assert_eq!(
+    error("name: foo"),
+    PackageError::InvalidType("name".to_string(), "a string", "foo".to_string(), (6, 9))
+  );
+  assert_eq!(
+    error("authors: [\"a\" b]"),
+    PackageError::InvalidType(
+      "authors".to_string(),
+      "a list of strings",
+      "b".to_string(),
+      (14, 15)
+    )
+  );
+  assert_eq!(
+    error("build: [[\"make\" [\"x\"]]]"),
+    PackageError::InvalidType(
+      "build".to_string(),
+      "a list of commands",
+      "[\"x\"]".to_string(),
+      (16, 21)
+    )
+  );
+  assert_eq!(
+    error("version: \"1.0 beta\""),
+    PackageError::InvalidVersion(
+      crate::version::VersionError::InvalidChar(' ', "1.0 beta".to_string()),
+      (9, 19)
+    )
+  );
+  assert_eq!(
+    error("name: \"a\"\nname: \"b\""),
+    PackageError::DuplicateField("name".to_string(), (10, 19))
+  );
+  assert_eq!(
+    error("foo { }"),
+    PackageError::UnknownSection("foo".to_string(), (0, 7))
+  );
+  assert_eq!(
+    error("url { src: \"a\" }\nurl { src: \"b\" }"),
+    PackageError::DuplicateField("url".to_string(), (17, 33))
+  );
+  assert_eq!(
+    error("url { src: \"a\" rev: \"b\" }"),
+    PackageError::UnknownField("rev".to_string(), (15, 23))
+  );
+  assert_eq!(
+    error("extra-source \"a\" { src: \"a\" }\nextra-source \"a\" { src: \"b\" }"),
+    PackageError::DuplicateField("extra-source \"a\"".to_string(), (30, 59))
+  );
+  assert_eq!(
+    error("extra-source { src: \"a\" }"),
+    PackageError::UnnamedSection("extra-source".to_string(), (0, 25))
+  );
+  assert!(matches!(
+    error("depends: [foo]"),
+    PackageError::Formula(crate::formula::FormulaError::NotAPackageFormula(
+      _,
+      (10, 13)
+    ))
+  ));
+}
+
+#[test]
+fn check_opam_file_accessors() {
+  use crate::formula::*;
+  let opam_str = r#"opam-version: "2.0"
+name: "foo"
+version: "0.1.0"
+maintainer: ["a@example.com" "b@example.com"]
+x-ci-accept-failures: ["macos-homebrew"]
+unknown-field: [foo bar]
+depends: ["dune" {>= "3.0"}]
+url {
+  src: "https://example.com/foo.tbz"
+}
+tags: "x"
+"#;
+  let mut opam = crate::parse(opam_str).unwrap();
+  assert_eq!(opam.opam_version(), Ok(Some("2.0".to_string())));
+  assert_eq!(opam.version(), Ok(Some("0.1.0".parse().unwrap())));
+  assert_eq!(
+    opam.maintainers(),
+    Ok(vec![
+      "a@example.com".to_string(),
+      "b@example.com".to_string()
+    ])
+  );
+  assert_eq!(opam.tags(), Ok(vec!["x".to_string()]));
+  assert_eq!(opam.authors(), Ok(vec![]));
+  assert_eq!(opam.synopsis(), Ok(None));
+  assert_eq!(opam.depends().unwrap().unwrap().package_names(), ["dune"]);
+
+  opam.set_version(&"0.2.0".parse().unwrap());
+  opam.set_maintainers(&["c@example.com".to_string()]);
+  let mut depends = opam.depends().unwrap().unwrap();
+  if let PackageFormula::And(lst) = &mut depends {
+    let mut atom = PackageAtom::new("alcotest");
+    atom.flags.push(DependencyFlag::WithTest);
+    lst.push(PackageFormula::Atom(atom));
+  }
+  opam.set_depends(&depends);
+  opam.set_synopsis("Foo");
+  assert_eq!(
+    crate::value::format_opam_file(&opam),
+    r#"opam-version : "2.0"
+name : "foo"
+version : "0.2.0"
+maintainer : "c@example.com"
+x-ci-accept-failures : ["macos-homebrew"]
+unknown-field : [foo bar]
+depends : ["dune" {>= "3.0"} "alcotest" {with-test}]
+url {
+src : "https://example.com/foo.tbz"
+}
+tags : "x"
+synopsis : "Foo"
+"#
+  );
+  assert_eq!(
+    opam
+      .remove_field("unknown-field")
+      .map(|v| crate::value::value_to_string(&v)),
+    Some("[foo bar]".to_string())
+  );
+  assert_eq!(opam.remove_field("unknown-field"), None);
+  assert!(opam.field("x-ci-accept-failures").is_some());
+
+  let opam = crate::parse("name: [\"foo\"]").unwrap();
+  assert_eq!(
+    opam.name(),
+    Err(crate::package::PackageError::InvalidType(
+      "name".to_string(),
+      "a string",
+      "[\"foo\"]".to_string(),
+      (6, 13)
+    ))
+  );
+}
+
+#[test]
+fn check_lint() {
+  use crate::lint::{lint, Severity};
+  let numbers = |str: &str| {
+    lint(&crate::parse(str).unwrap())
+      .iter()
+      .map(|d| (d.number, d.severity))
+      .collect::<Vec<_>>()
+  };
+
+  let good = r#"
+opam-version: "2.0"
+name: "foo"
+synopsis: "A package"
+maintainer: "foo@example.com"
+authors: ["Foo" "Bar"]
+license: "LGPL-2.1-only WITH OCaml-LGPL-linking-exception"
+homepage: "https://example.com/foo"
+bug-reports: "https://example.com/foo/issues"
+dev-repo: "git+https://example.com/foo.git"
+depends: ["dune" {>= "3.0"} "alcotest" {with-test}]
+build: [
+  ["dune" "build" "-p" name "-j" jobs "@runtest" {with-test}]
+  ["%{dune:bin}%/foo" "--prefix=%{prefix}%"]
+]
+"#;
+  assert_eq!(numbers(good), []);
+
+  assert_eq!(
+    numbers("name: \"foo\""),
+    [
+      (1, Severity::Error),
+      (23, Severity::Error),
+      (25, Severity::Warning),
+      (35, Severity::Warning),
+      (36, Severity::Warning),
+      (37, Severity::Warning),
+      (57, Severity::Error),
+      (68, Severity::Warning),
+    ]
+  );
+
+  let bad = r#"
+opam-version: "2.0.1"
+synopsis: "A package"
+synopsis: "A package"
+maintainer: []
+authors: "Foo"
+license: ["MIT" "Public domain" "GPL-2.0+ OR (Apache-2.0 AND MIT)"]
+homepage: "https://example.com/foo"
+bug-reports: "https://example.com/foo/issues"
+dev-repo: "git+https://example.com/foo.git"
+depends: "dune"
+ocaml-version: [>= "4.08"]
+build-test: [make "test"]
+build: [make "PREFIX=%{prefix}%" "%{bar:lib}%" foo:share] {undefined-var}
+install: [make "install" "%{unknown}%" "%{bar:share}%"]
+"#;
+  let diagnostics = lint(&crate::parse(bad).unwrap());
+  assert_eq!(
+    diagnostics
+      .iter()
+      .map(|d| (d.number, d.pos))
+      .collect::<Vec<_>>(),
+    [
+      (3, Some((45, 66))),
+      (20, Some((15, 22))),
+      (22, Some((79, 81))),
+      (32, Some((307, 333))),
+      (41, Some((393, 406))),
+      (48, Some((334, 359))),
+      (58, Some((419, 432))),
+      (62, Some((113, 128))),
+    ]
+  );
+  assert_eq!(
+    diagnostics[4].to_string(),
+    "warning 41: Some packages are mentioned in package scripts or features, but there is no dependency or depopt toward them: bar, foo"
+  );
+  assert_eq!(
+    diagnostics[6].to_string(),
+    "warning 58: Undefined variables in commands: undefined-var, unknown"
+  );
+
+  let licenses = r#"
+opam-version: "2.0"
+synopsis: "A package"
+license: ["LGPL-2.0" "MPL-1.0 OR CECILL-2.0" "CC-BY-SA-3.0 WITH OCaml-LGPL-linking-exception" "GPL-2.0+" "LicenseRef-foo" "MIT OR Foo-1.0" "MIT WITH Some-exception" "MIT AND" "MIT WITH"]
+"#;
+  let diagnostics = lint(&crate::parse(licenses).unwrap());
+  assert_eq!(
+    diagnostics
+      .iter()
+      .filter(|d| d.number == 62)
+      .map(|d| d.pos)
+      .collect::<Vec<_>>(),
+    [
+      Some((165, 181)),
+      Some((182, 207)),
+      Some((208, 217)),
+      Some((218, 228))
+    ]
+  );
+}
+
+#[test]
+fn check_install_file() {
+  use crate::install::{InstallEntry, InstallFile, InstallSection};
+  use crate::package::PackageError;
+  use std::path::{Path, PathBuf};
+  let opam = crate::parse(
+    r#"
+lib_root: ["_build/install/default/lib/foo/META" {"foo/META"}]
+libexec: "?_build/foo.cmxs"
+share_root: ["data/foo.dat"]
+stublibs: ["_build/dllfoo_stubs.so"]
+doc: ["README.md" "CHANGES.md" {"changes.md"}]
+man: ["doc/foo.3o" "doc/foo.txt" "doc/bar.1" {"man1/baz.1"}]
+misc: ["foo.conf" {"/etc/foo.conf"}]
+"#,
+  )
+  .unwrap();
+  let install = InstallFile::from_opam_file(&opam).unwrap();
+  assert_eq!(
+    install.libexec,
+    [InstallEntry {
+      src: "_build/foo.cmxs".to_string(),
+      optional: true,
+      dst: None,
+    }]
+  );
+  assert_eq!(install.doc[1].dst.as_deref(), Some("changes.md"));
+  assert!(install.lib.is_empty());
+  assert_eq!(install.section(InstallSection::Man).len(), 3);
+  assert_eq!(
+    install
+      .install_paths(Path::new("/opam/default"), "foo")
+      .into_iter()
+      .map(|(section, _, path)| (section.as_str(), path))
+      .collect::<Vec<_>>(),
+    [
+      ("lib_root", PathBuf::from("/opam/default/lib/foo/META")),
+      ("libexec", PathBuf::from("/opam/default/lib/foo/foo.cmxs")),
+      ("share_root", PathBuf::from("/opam/default/share/foo.dat")),
+      ("doc", PathBuf::from("/opam/default/doc/foo/README.md")),
+      ("doc", PathBuf::from("/opam/default/doc/foo/changes.md")),
+      (
+        "stublibs",
+        PathBuf::from("/opam/default/lib/stublibs/dllfoo_stubs.so")
+      ),
+      ("man", PathBuf::from("/opam/default/man/man3/foo.3o")),
+      ("man", PathBuf::from("/opam/default/man/foo.txt")),
+      ("man", PathBuf::from("/opam/default/man/man1/baz.1")),
+      ("misc", PathBuf::from("/etc/foo.conf")),
+    ]
+  );
+
+  let error = |str: &str| InstallFile::from_opam_file(&crate::parse(str).unwrap()).unwrap_err();
+  assert_eq!(
+    error("bin: [foo]"),
+    PackageError::InvalidType(
+      "bin".to_string(),
+      "a list of files",
+      "foo".to_string(),
+      (6, 9)
+    )
+  );
+  assert_eq!(
+    error("bin: [\"a\" {\"b\" \"c\"}]"),
+    PackageError::InvalidType(
+      "bin".to_string(),
+      "a list of files",
+      "\"a\" {\"b\" \"c\"}".to_string(),
+      (6, 19)
+    )
+  );
+  assert_eq!(
+    error("bin: \"a\"\nbin: \"b\""),
+    PackageError::DuplicateField("bin".to_string(), (9, 17))
+  );
+  assert_eq!(
+    error("binaries: \"a\""),
+    PackageError::UnknownField("binaries".to_string(), (0, 13))
+  );
+  assert_eq!(
+    error("misc: [\"foo.conf\"]"),
+    PackageError::MissingDestination("foo.conf".to_string(), (6, 18))
+  );
+  assert_eq!(
+    error("misc: [\"foo.conf\" {\"etc/foo.conf\"}]"),
+    PackageError::MissingDestination("foo.conf".to_string(), (6, 35))
+  );
+  assert_eq!(
+    error("lib { }"),
+    PackageError::UnknownSection("lib".to_string(), (0, 7))
+  );
+}
+
+#[test]
+fn check_config_file() {
+  use crate::config::{ConfigFile, ConfigValue};
+  use crate::package::PackageError;
+  let opam = crate::parse(
+    r#"
+opam-version: "2.0"
+file-depends: ["/usr/bin/foo" "md5=0123"]
+variables {
+  preinstalled: false
+  version: "1.2.3"
+  libs: ["foo" "bar"]
+}
+"#,
+  )
+  .unwrap();
+  let config = ConfigFile::from_opam_file(&opam).unwrap();
+  assert_eq!(config.opam_version.as_deref(), Some("2.0"));
+  assert_eq!(
+    config.file_depends,
+    [("/usr/bin/foo".to_string(), "md5=0123".to_string())]
+  );
+  assert_eq!(config.get("preinstalled"), Some(&ConfigValue::Bool(false)));
+  assert_eq!(
+    config.get("libs"),
+    Some(&ConfigValue::List(vec![
+      "foo".to_string(),
+      "bar".to_string()
+    ]))
+  );
+  assert_eq!(config.get("libs").unwrap().to_string(), "foo bar");
+  assert_eq!(config.get("missing"), None);
+
+  let str = crate::value::format_opam_file(&config.to_opam_file());
+  assert_eq!(
+    str,
+    r#"opam-version : "2.0"
+variables {
+preinstalled : false
+version : "1.2.3"
+libs : ["foo" "bar"]
+}
+file-depends : [["/usr/bin/foo" "md5=0123"]]
+"#
+  );
+  assert_eq!(
+    ConfigFile::from_opam_file(&crate::parse(&str).unwrap()).unwrap(),
+    config
+  );
+  assert_eq!(ConfigFile::default().to_opam_file().file_contents, []);
+
+  let error = |str: &str| ConfigFile::from_opam_file(&crate::parse(str).unwrap()).unwrap_err();
+  assert_eq!(
+    error("variables { foo: bar }"),
+    PackageError::InvalidType(
+      "foo".to_string(),
+      "a bool, a string or a list of strings",
+      "bar".to_string(),
+      (17, 20)
+    )
+  );
+  assert_eq!(
+    error("variables { foo: true foo: false }"),
+    PackageError::DuplicateField("foo".to_string(), (22, 32))
+  );
+  assert_eq!(
+    error("variables { foo: true }\nvariables { bar: true }"),
+    PackageError::DuplicateField("variables".to_string(), (24, 47))
+  );
+  assert_eq!(
+    error("url { src: \"a\" }"),
+    PackageError::UnknownSection("url".to_string(), (0, 16))
+  );
+
+  let config =
+    ConfigFile::from_opam_file(&crate::parse("x-foo: \"a\"\ndepends: []").unwrap()).unwrap();
+  assert_eq!(
+    config
+      .extra_fields
+      .iter()
+      .map(|(name, _)| name.as_str())
+      .collect::<Vec<_>>(),
+    ["x-foo", "depends"]
+  );
+  assert_eq!(
+    crate::value::format_opam_file(&config.to_opam_file()),
+    "x-foo : \"a\"\ndepends : []\n"
+  );
+}
+
+#[test]
+fn check_root_config() {
+  use crate::config::ConfigValue;
+  use crate::package::PackageError;
+  use crate::root::{RootConfig, SwitchConfig};
+  let opam = crate::parse(
+    r#"
+opam-version: "2.0"
+repositories: ["default" "local"]
+installed-switches: ["default"]
+switch: "default"
+download-command: ["curl" "-L" "-o" out url]
+download-jobs: 3
+wrap-build-commands: ["%{hooks}%/sandbox.sh" "build"] {os = "linux"}
+global-variables: [sys-ocaml-libc "libc" "Host C Runtime Library type of the OCaml compiler present on your system"]
+eval-variables: [[sys-ocaml-cc ["ocamlc" "-config-var" "c_compiler"] "C compiler"]]
+default-compiler: ["ocaml-system" | "ocaml-base-compiler"]
+depext: true
+depext-cannot-install: false
+x-custom: "foo"
+"#,
+  )
+  .unwrap();
+  let config = RootConfig::from_opam_file(&opam).unwrap();
+  assert_eq!(config.repositories, ["default", "local"]);
+  assert_eq!(config.download_command.as_ref().unwrap().args.len(), 5);
+  assert_eq!(config.download_jobs, Some(3));
+  assert_eq!(config.jobs, None);
+  assert_eq!(config.wrap_build_commands.len(), 1);
+  assert!(config.wrap_build_commands[0].filter.is_some());
+  assert_eq!(
+    config.global_variables[0].value,
+    ConfigValue::String("libc".to_string())
+  );
+  assert_eq!(config.eval_variables[0].name, "sys-ocaml-cc");
+  assert_eq!(
+    config.default_compiler.as_ref().unwrap().package_names(),
+    ["ocaml-system", "ocaml-base-compiler"]
+  );
+  assert_eq!(config.depext, Some(true));
+  assert_eq!(config.depext_cannot_install, Some(false));
+  assert_eq!(config.extra_fields[0].0, "x-custom");
+  let str = crate::value::format_opam_file(&config.to_opam_file());
+  assert_eq!(
+    str,
+    r#"opam-version : "2.0"
+repositories : ["default" "local"]
+installed-switches : ["default"]
+switch : "default"
+download-command : ["curl" "-L" "-o" out url]
+download-jobs : 3
+wrap-build-commands : [["%{hooks}%/sandbox.sh" "build"] {os = "linux"}]
+global-variables : [[sys-ocaml-libc "libc" "Host C Runtime Library type of the OCaml compiler present on your system"]]
+eval-variables : [[sys-ocaml-cc ["ocamlc" "-config-var" "c_compiler"] "C compiler"]]
+default-compiler : [("ocaml-system" | "ocaml-base-compiler")]
+depext : true
+depext-cannot-install : false
+x-custom : "foo"
+"#
+  );
+  let reparsed = RootConfig::from_opam_file(&crate::parse(&str).unwrap()).unwrap();
+  assert_eq!(
+    crate::value::format_opam_file(&reparsed.to_opam_file()),
+    str
+  );
+
+  let opam = crate::parse(
+    r#"
+opam-version: "2.0"
+synopsis: "OCaml 5.1.1"
+setenv: [PATH += "/opt/bin" CC := "clang"]
+invariant: ["ocaml-base-compiler" {= "5.1.1"}]
+paths {
+  doc: "/usr/share/doc"
+}
+variables {
+  sys-ocaml-version: "5.1.1"
+  native: true
+}
+"#,
+  )
+  .unwrap();
+  let config = SwitchConfig::from_opam_file(&opam).unwrap();
+  assert_eq!(config.synopsis.as_deref(), Some("OCaml 5.1.1"));
+  assert_eq!(config.repositories, None);
+  assert_eq!(config.setenv[1].variable, "CC");
+  assert_eq!(
+    config.paths,
+    [("doc".to_string(), "/usr/share/doc".to_string())]
+  );
+  assert_eq!(
+    config.variables[1],
+    ("native".to_string(), ConfigValue::Bool(true))
+  );
+  let str = crate::value::format_opam_file(&config.to_opam_file());
+  assert_eq!(
+    str,
+    r#"opam-version : "2.0"
+synopsis : "OCaml 5.1.1"
+setenv : [PATH += "/opt/bin" CC := "clang"]
+invariant : ["ocaml-base-compiler" {= "5.1.1"}]
+paths {
+doc : "/usr/share/doc"
+}
+variables {
+sys-ocaml-version : "5.1.1"
+native : true
+}
+"#
+  );
+  assert_eq!(
+    SwitchConfig::from_opam_file(&crate::parse(&str).unwrap()).unwrap(),
+    config
+  );
+
+  let root_error = |str: &str| RootConfig::from_opam_file(&crate::parse(str).unwrap()).unwrap_err();
+  assert_eq!(
+    root_error("jobs: \"8\""),
+    PackageError::InvalidType(
+      "jobs".to_string(),
+      "a non-negative integer",
+      "\"8\"".to_string(),
+      (6, 9)
+    )
+  );
+  assert_eq!(
+    root_error("switch: \"a\"\nswitch: \"b\""),
+    PackageError::DuplicateField("switch".to_string(), (12, 23))
+  );
+  assert_eq!(
+    root_error("global-variables: [[foo \"a\"]]"),
+    PackageError::InvalidType(
+      "global-variables".to_string(),
+      "a list of variables",
+      "[foo \"a\"]".to_string(),
+      (19, 28)
+    )
+  );
+  assert_eq!(
+    root_error("paths { bin: \"/bin\" }"),
+    PackageError::UnknownSection("paths".to_string(), (0, 21))
+  );
+  let switch_error =
+    |str: &str| SwitchConfig::from_opam_file(&crate::parse(str).unwrap()).unwrap_err();
+  assert_eq!(
+    switch_error("variables { foo: 1 }"),
+    PackageError::InvalidType(
+      "foo".to_string(),
+      "a bool, a string or a list of strings",
+      "1".to_string(),
+      (17, 18)
+    )
+  );
+  assert_eq!(
+    switch_error("paths { bin: \"/a\" }\npaths { lib: \"/b\" }"),
+    PackageError::DuplicateField("paths".to_string(), (20, 39))
+  );
+  assert_eq!(
+    switch_error("variables { a: true }\nvariables { b: true }"),
+    PackageError::DuplicateField("variables".to_string(), (22, 43))
+  );
+}
+
+#[test]
+fn check_switch_state() {
+  use crate::package::{OpamPackage, PackageError};
+  use crate::switch::{PackageVersion, SwitchExport, SwitchState};
+  let package = "ocaml-base-compiler.5.1.1~rc1"
+    .parse::<PackageVersion>()
+    .unwrap();
+  assert_eq!(package.name, "ocaml-base-compiler");
+  assert_eq!(package.version.as_str(), "5.1.1~rc1");
+  assert!("ocaml".parse::<PackageVersion>().is_err());
+
+  let opam = crate::parse(
+    r#"
+opam-version: "2.0"
+compiler: ["ocaml-system.5.1.1"]
+roots: ["foo.dev" "ocaml-system.5.1.1"]
+installed: ["foo.dev" "ocaml.5.1.1" "ocaml-system.5.1.1"]
+pinned: "foo.dev"
+package "foo" {
+  opam-version: "2.0"
+  name: "foo"
+  depends: ["ocaml"]
+  url {
+    src: "git+https://example.com/foo.git#main"
+  }
+}
+"#,
+  )
+  .unwrap();
+  let export = SwitchExport::from_opam_file(&opam).unwrap();
+  assert_eq!(export.state.pinned[0].to_string(), "foo.dev");
+  assert_eq!(export.state.installed.len(), 3);
+  let foo = OpamPackage::from_opam_file(&export.packages["foo"]).unwrap();
+  assert_eq!(
+    foo.url.unwrap().src.as_deref(),
+    Some("git+https://example.com/foo.git#main")
+  );
+  let str = crate::value::format_opam_file(&export.to_opam_file());
+  assert_eq!(
+    str,
+    r#"opam-version : "2.0"
+compiler : ["ocaml-system.5.1.1"]
+roots : ["foo.dev" "ocaml-system.5.1.1"]
+installed : ["foo.dev" "ocaml.5.1.1" "ocaml-system.5.1.1"]
+pinned : ["foo.dev"]
+package "foo" {
+opam-version : "2.0"
+name : "foo"
+depends : ["ocaml"]
+url {
+src : "git+https://example.com/foo.git#main"
+}
+}
+"#
+  );
+  let reparsed = SwitchExport::from_opam_file(&crate::parse(&str).unwrap()).unwrap();
+  assert_eq!(reparsed.state, export.state);
+  assert_eq!(
+    crate::value::format_opam_file(&reparsed.to_opam_file()),
+    str
+  );
+  assert!(SwitchState::from_opam_file(&opam).is_err());
+
+  let state = SwitchState::from_opam_file(
+    &crate::parse("installed: [\"ocaml.4.14.1\" \"dune.3.12.1\" \"zarith.1.13\"]").unwrap(),
+  )
+  .unwrap();
+  let diff = state.diff(&export.state);
+  assert_eq!(
+    diff.added.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+    ["foo.dev", "ocaml-system.5.1.1"]
+  );
+  assert_eq!(
+    diff
+      .removed
+      .iter()
+      .map(|p| p.to_string())
+      .collect::<Vec<_>>(),
+    ["dune.3.12.1", "zarith.1.13"]
+  );
+  assert_eq!(diff.changed.len(), 1);
+  assert_eq!(diff.changed[0].0.to_string(), "ocaml.4.14.1");
+  assert_eq!(diff.changed[0].1.as_str(), "5.1.1");
+  assert_eq!(state.installed_version("zarith").unwrap().as_str(), "1.13");
+  assert_eq!(state.diff(&state), Default::default());
+
+  let error = |str: &str| SwitchState::from_opam_file(&crate::parse(str).unwrap()).unwrap_err();
+  assert_eq!(
+    error("roots: [\"ocaml\"]"),
+    PackageError::InvalidType(
+      "roots".to_string(),
+      "a list of packages as `name.version`",
+      "\"ocaml\"".to_string(),
+      (8, 15)
+    )
+  );
+  assert_eq!(
+    error("roots: [\"ocaml.5/1\"]"),
+    PackageError::InvalidVersion(
+      crate::version::VersionError::InvalidChar('/', "5/1".to_string()),
+      (8, 19)
+    )
+  );
+}
+
+#[test]
+fn check_repository_files() {
+  use crate::package::PackageError;
+  use crate::repository::{parse_descr, parse_url_file, Descr, RepoFile};
+  let opam = crate::parse(
+    r#"
+opam-version: "2.0"
+upstream: "https://github.com/ocaml/opam-repository/tree/master/"
+redirect: [
+  "https://opam.ocaml.org/1.1" {opam-version < "1.2"}
+  "https://example.com/repo"
+]
+announce: "Please upgrade" {opam-version < "2.1"}
+dl-cache: ["https://opam.ocaml.org/cache"]
+x-foo: true
+"#,
+  )
+  .unwrap();
+  let repo = RepoFile::from_opam_file(&opam).unwrap();
+  assert_eq!(repo.redirect.len(), 2);
+  assert!(repo.redirect[0].filter.is_some());
+  assert_eq!(repo.redirect[1].filter, None);
+  assert_eq!(repo.announce[0].value, "Please upgrade");
+  assert_eq!(repo.dl_cache, ["https://opam.ocaml.org/cache"]);
+  assert_eq!(repo.extra_fields[0].0, "x-foo");
+  assert_eq!(
+    crate::value::format_opam_file(&repo.to_opam_file()),
+    r#"opam-version : "2.0"
+upstream : "https://github.com/ocaml/opam-repository/tree/master/"
+redirect : ["https://opam.ocaml.org/1.1" {opam-version < "1.2"} "https://example.com/repo"]
+announce : ["Please upgrade" {opam-version < "2.1"}]
+dl-cache : ["https://opam.ocaml.org/cache"]
+x-foo : true
+"#
+  );
+  assert_eq!(
+    RepoFile::from_opam_file(&crate::parse("browse: [\"a\"]").unwrap()),
+    Err(PackageError::InvalidType(
+      "browse".to_string(),
+      "a string",
+      "[\"a\"]".to_string(),
+      (8, 13)
+    ))
+  );
+
+  let url = |str: &str| parse_url_file(&crate::parse(str).unwrap()).unwrap();
+  let archive = url(
+    r#"
+archive: "https://example.com/foo-1.0.tar.gz"
+checksum: "d41d8cd98f00b204e9800998ecf8427e"
+mirrors: "https://mirror.example.com/foo-1.0.tar.gz"
+"#,
+  );
+  assert_eq!(
+    archive.src.as_deref(),
+    Some("https://example.com/foo-1.0.tar.gz")
+  );
+  assert_eq!(archive.checksum, ["md5=d41d8cd98f00b204e9800998ecf8427e"]);
+  assert_eq!(
+    archive.mirrors,
+    ["https://mirror.example.com/foo-1.0.tar.gz"]
+  );
+  assert_eq!(url("checksum: \"sha256=0123\"").checksum, ["sha256=0123"]);
+  assert_eq!(
+    url("git: \"git://example.com/foo.git\"").src.as_deref(),
+    Some("git://example.com/foo.git")
+  );
+  assert_eq!(
+    url("hg: \"https://example.com/foo#default\"")
+      .src
+      .as_deref(),
+    Some("hg+https://example.com/foo#default")
+  );
+  assert_eq!(
+    parse_url_file(&crate::parse("src: \"a\"\nrev: \"b\"").unwrap()),
+    Err(PackageError::UnknownField("rev".to_string(), (9, 17)))
+  );
+
+  let descr = parse_descr("Foo library\r\nFoo does\nthings.\n\n");
+  assert_eq!(
+    descr,
+    Descr {
+      synopsis: "Foo library".to_string(),
+      description: "Foo does\nthings.".to_string(),
+    }
+  );
+  assert_eq!(descr.to_string(), "Foo library\n\nFoo does\nthings.\n");
+  assert_eq!(parse_descr(&descr.to_string()), descr);
+  assert_eq!(parse_descr("Foo").to_string(), "Foo\n");
+  assert_eq!(parse_descr(""), Descr::default());
+}
@@ -129,25 +129,147 @@ pub enum EnvUpdateOpKind {
 
 pub type Pos = (usize, usize);
 
+/// A value built rather than parsed, at the position `(0, 0)`.
+pub(crate) fn make_value(kind: ValueKind) -> Value {
+  Value { kind, pos: (0, 0) }
+}
+
+// Positions differ between two sources of the same file, e.g. before and after formatting.
+// `strip_positions` resets every `Pos` to `(0, 0)` so that such values compare equal.
+
+impl OpamFile {
+  /// Set every position in the file to `(0, 0)`.
+  pub fn strip_positions(&mut self) {
+    self
+      .file_contents
+      .iter_mut()
+      .for_each(OpamFileItem::strip_positions)
+  }
+
+  /// Equality ignoring positions.
+  pub fn eq_ignoring_positions(&self, other: &OpamFile) -> bool {
+    let (mut l, mut r) = (self.clone(), other.clone());
+    l.strip_positions();
+    r.strip_positions();
+    l == r
+  }
+}
+
+impl OpamFileItem {
+  pub fn strip_positions(&mut self) {
+    match self {
+      OpamFileItem::Section(pos, section) => {
+        *pos = (0, 0);
+        section.strip_positions()
+      }
+      OpamFileItem::Variable(pos, _, value) => {
+        *pos = (0, 0);
+        value.strip_positions()
+      }
+    }
+  }
+}
+
+impl OpamFileSection {
+  pub fn strip_positions(&mut self) {
+    self
+      .section_item
+      .iter_mut()
+      .for_each(OpamFileItem::strip_positions)
+  }
+}
+
+impl Value {
+  /// Set every position in the value, including those of operators, to `(0, 0)`.
+  pub fn strip_positions(&mut self) {
+    self.pos = (0, 0);
+    match &mut self.kind {
+      ValueKind::Bool(_) | ValueKind::Int(_) | ValueKind::String(_) | ValueKind::Ident(_) => {}
+      ValueKind::RelOp(op, l, r) => {
+        op.strip_positions();
+        l.strip_positions();
+        r.strip_positions()
+      }
+      ValueKind::PrefixRelOp(op, v) => {
+        op.strip_positions();
+        v.strip_positions()
+      }
+      ValueKind::LogOp(op, l, r) => {
+        op.strip_positions();
+        l.strip_positions();
+        r.strip_positions()
+      }
+      ValueKind::PfxOp(op, v) => {
+        op.strip_positions();
+        v.strip_positions()
+      }
+      ValueKind::List(lst) | ValueKind::Group(lst) => {
+        lst.iter_mut().for_each(Value::strip_positions)
+      }
+      ValueKind::Option(v, lst) => {
+        v.strip_positions();
+        lst.iter_mut().for_each(Value::strip_positions)
+      }
+      ValueKind::EnvBinding(l, op, r) => {
+        op.strip_positions();
+        l.strip_positions();
+        r.strip_positions()
+      }
+    }
+  }
+
+  /// Equality ignoring positions.
+  pub fn eq_ignoring_positions(&self, other: &Value) -> bool {
+    let (mut l, mut r) = (self.clone(), other.clone());
+    l.strip_positions();
+    r.strip_positions();
+    l == r
+  }
+}
+
+impl RelOp {
+  pub fn strip_positions(&mut self) {
+    self.pos = (0, 0)
+  }
+}
+
+impl LogOp {
+  pub fn strip_positions(&mut self) {
+    self.pos = (0, 0)
+  }
+}
+
+impl PfxOp {
+  pub fn strip_positions(&mut self) {
+    self.pos = (0, 0)
+  }
+}
+
+impl EnvUpdateOp {
+  pub fn strip_positions(&mut self) {
+    self.pos = (0, 0)
+  }
+}
+
 /// Convert to an OPAM file format.
 pub fn format_opam_file(input: &OpamFile) -> String {
   let file_contents = &input.file_contents;
   opam_file_item_vec_to_string(file_contents)
 }
 
-fn opam_file_item_vec_to_string(value: &Vec<OpamFileItem>) -> String {
+fn opam_file_item_vec_to_string(value: &[OpamFileItem]) -> String {
   value
     .iter()
     .map(|file_content| {
       let str = match file_content {
         OpamFileItem::Section(_, opam_file_section) => {
           let kind = &opam_file_section.section_kind;
-          let section_name = opam_file_section
-            .clone()
-            .section_name
-            .unwrap_or_else(|| String::new());
+          let section_name = match &opam_file_section.section_name {
+            Some(name) => format!("{} ", string_literal(name)),
+            None => String::new(),
+          };
           let section_item_str = opam_file_item_vec_to_string(&opam_file_section.section_item);
-          format!("{} {} {{{}}}", kind, section_name, section_item_str)
+          format!("{} {}{{\n{}}}", kind, section_name, section_item_str)
         }
         OpamFileItem::Variable(_, ident, value) => {
           format!("{} : {}", ident, value_to_string(value))
@@ -158,74 +280,184 @@ fn opam_file_item_vec_to_string(value: &Vec<OpamFileItem>) -> String {
     .collect::<String>()
 }
 
-fn value_to_string(value: &Value) -> String {
+pub(crate) fn value_to_string(value: &Value) -> String {
   match &value.kind {
     ValueKind::Bool(b) => b.to_string(),
     ValueKind::Int(i) => i.to_string(),
-    ValueKind::String(str) => format!("{:?}", str),
+    ValueKind::String(str) => string_literal(str),
     ValueKind::Ident(str) => str.to_string(),
-    ValueKind::List(lst) => {
-      format!(
-        "[{}]",
-        lst
-          .iter()
-          .map(|value| format!("{} ", value_to_string(value)))
-          .collect::<String>()
-      )
-    }
-    ValueKind::Group(lst) => {
-      format!(
-        "({})",
-        lst
-          .iter()
-          .map(|value| format!("{} ", value_to_string(value)))
-          .collect::<String>()
-      )
-    }
+    ValueKind::List(lst) => format!("[{}]", values_to_string(lst)),
+    ValueKind::Group(lst) => format!("({})", values_to_string(lst)),
     ValueKind::Option(v, lst) => {
       format!(
         "{} {{{}}}",
-        lst
-          .iter()
-          .map(|value| format!("{} ", value_to_string(value)))
-          .collect::<String>(),
-        value_to_string(v)
+        operand_to_string(v, Precedence::Option),
+        values_to_string(lst)
       )
     }
     ValueKind::RelOp(op, l, r) => {
       format!(
         "{} {} {}",
-        value_to_string(l),
+        operand_to_string(l, Precedence::Atom),
         relop_to_string(&op.kind),
-        value_to_string(r),
+        operand_to_string(r, Precedence::Atom),
       )
     }
     ValueKind::PrefixRelOp(op, r) => {
-      format!("{} {}", relop_to_string(&op.kind), value_to_string(r),)
+      format!(
+        "{} {}",
+        relop_to_string(&op.kind),
+        operand_to_string(r, Precedence::Atom),
+      )
     }
     ValueKind::LogOp(op, l, r) => {
+      let (l_prec, r_prec) = logop_operand_precedences(&op.kind);
       format!(
         "{} {} {}",
-        value_to_string(l),
+        operand_to_string(l, l_prec),
         logop_to_string(&op.kind),
-        value_to_string(r),
+        operand_to_string(r, r_prec),
       )
     }
     ValueKind::PfxOp(op, r) => {
-      format!("{} {}", pfxop_to_string(&op.kind), value_to_string(r),)
+      format!(
+        "{} {}",
+        pfxop_to_string(&op.kind),
+        operand_to_string(r, Precedence::Prefix),
+      )
     }
     ValueKind::EnvBinding(l, op, r) => {
       format!(
         "{} {} {}",
-        value_to_string(l),
+        operand_to_string(l, Precedence::Atom),
         envop_to_string(&op.kind),
-        value_to_string(r),
+        operand_to_string(r, Precedence::Atom),
       )
     }
   }
 }
 
-fn relop_to_string(op: &RelOpKind) -> String {
+/// How tightly a value binds, from loosest to tightest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Precedence {
+  /// `<value> | <value>`
+  Or,
+  /// `<value> & <value>`
+  And,
+  /// `! <value>`, `? <value>`
+  Prefix,
+  /// `<value> { <value>* }`
+  Option,
+  /// Lists, groups and binary or prefix relational operators
+  Primary,
+  /// Bools, ints, strings and idents
+  Atom,
+}
+
+pub(crate) fn precedence(value: &Value) -> Precedence {
+  match &value.kind {
+    ValueKind::Bool(_) | ValueKind::Int(_) | ValueKind::String(_) | ValueKind::Ident(_) => {
+      Precedence::Atom
+    }
+    ValueKind::List(_)
+    | ValueKind::Group(_)
+    | ValueKind::RelOp(_, _, _)
+    | ValueKind::PrefixRelOp(_, _)
+    | ValueKind::EnvBinding(_, _, _) => Precedence::Primary,
+    ValueKind::Option(_, _) => Precedence::Option,
+    ValueKind::PfxOp(_, _) => Precedence::Prefix,
+    ValueKind::LogOp(op, _, _) => match op.kind {
+      LogOpKind::Or => Precedence::Or,
+      LogOpKind::And => Precedence::And,
+    },
+  }
+}
+
+/// The precedences the left and right operands of a `&` or `|` must have to be read back without parentheses.
+pub(crate) fn logop_operand_precedences(op: &LogOpKind) -> (Precedence, Precedence) {
+  // `&` binds tighter than `|`, and both are left-associative
+  match op {
+    LogOpKind::Or => (Precedence::Or, Precedence::And),
+    LogOpKind::And => (Precedence::And, Precedence::Prefix),
+  }
+}
+
+/// `l <op> r`, with parentheses where the precedence of `l` or `r` requires them.
+pub(crate) fn logop_value(kind: LogOpKind, l: Value, r: Value) -> Value {
+  let group = |v: Value, min: Precedence| {
+    if precedence(&v) < min {
+      make_value(ValueKind::Group(vec![v]))
+    } else {
+      v
+    }
+  };
+  let (l_prec, r_prec) = logop_operand_precedences(&kind);
+  make_value(ValueKind::LogOp(
+    LogOp { kind, pos: (0, 0) },
+    Box::new(group(l, l_prec)),
+    Box::new(group(r, r_prec)),
+  ))
+}
+
+/// Print `value` where the grammar expects a value binding at least as tightly as `min`,
+/// wrapping it in parentheses otherwise.
+fn operand_to_string(value: &Value, min: Precedence) -> String {
+  if precedence(value) < min {
+    format!("({})", value_to_string(value))
+  } else {
+    value_to_string(value)
+  }
+}
+
+/// Whether `value` is printed starting with a `<relop>`.
+pub(crate) fn starts_with_relop(value: &Value) -> bool {
+  match &value.kind {
+    ValueKind::PrefixRelOp(_, _) => true,
+    ValueKind::LogOp(_, l, _) | ValueKind::Option(l, _) => starts_with_relop(l),
+    _ => false,
+  }
+}
+
+fn values_to_string(lst: &[Value]) -> String {
+  lst
+    .iter()
+    .enumerate()
+    .map(|(i, value)| {
+      // `a >= b` is read back as a binary relop, so `>= b` must be parenthesized after another value
+      if i > 0 && starts_with_relop(value) {
+        format!("({})", value_to_string(value))
+      } else {
+        value_to_string(value)
+      }
+    })
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// Quote `str` as an opam string literal, using `"""` if it spans several lines.
+pub(crate) fn string_literal(str: &str) -> String {
+  let triple = str.contains('\n');
+  let chars = str.chars().collect::<Vec<_>>();
+  let mut literal = String::new();
+  for (i, c) in chars.iter().enumerate() {
+    match c {
+      '\\' => literal.push('\\'),
+      // inside `"""`, a quote only needs escaping if it would close the string
+      '"' if !triple || chars[i..].starts_with(&['"', '"', '"']) || i == chars.len() - 1 => {
+        literal.push('\\')
+      }
+      _ => (),
+    }
+    literal.push(*c);
+  }
+  if triple {
+    format!("\"\"\"{}\"\"\"", literal)
+  } else {
+    format!("\"{}\"", literal)
+  }
+}
+
+pub(crate) fn relop_to_string(op: &RelOpKind) -> String {
   match op {
     RelOpKind::Eq => "=".to_string(),
     RelOpKind::Neq => "!=".to_string(),
@@ -237,21 +469,21 @@ fn relop_to_string(op: &RelOpKind) -> String {
   }
 }
 
-fn logop_to_string(op: &LogOpKind) -> String {
+pub(crate) fn logop_to_string(op: &LogOpKind) -> String {
   match op {
     LogOpKind::And => "&".to_string(),
     LogOpKind::Or => "|".to_string(),
   }
 }
 
-fn pfxop_to_string(op: &PfxOpKind) -> String {
+pub(crate) fn pfxop_to_string(op: &PfxOpKind) -> String {
   match op {
     PfxOpKind::Not => "!".to_string(),
     PfxOpKind::Defined => "?".to_string(),
   }
 }
 
-fn envop_to_string(op: &EnvUpdateOpKind) -> String {
+pub(crate) fn envop_to_string(op: &EnvUpdateOpKind) -> String {
   match op {
     EnvUpdateOpKind::PlusEq => "+=".to_string(),
     EnvUpdateOpKind::EqPlus => "=+".to_string(),
@@ -0,0 +1,178 @@
+//! Comparison of version strings.
+//!
+//! See more [Version ordering](https://opam.ocaml.org/doc/Manual.html#Version-ordering)
+//!
+//! Versions are compared as in Debian: alternately the longest non-digit prefixes, char by char,
+//! and the longest digit prefixes, numerically. Letters sort before other chars,
+//! and `~` sorts before everything, even the end of the version.
+//!
+//! ```rust
+//! use std::cmp::Ordering;
+//! use opam_file_rs::version::compare;
+//! assert_eq!(compare("1.10", "1.9"), Ordering::Greater);
+//! assert_eq!(compare("1.0~beta", "1.0"), Ordering::Less);
+//! assert_eq!(compare("1.01", "1.1"), Ordering::Equal);
+//! ```
+//!
+//! [`OpamVersion`] is a version string ordered that way:
+//!
+//! ```rust
+//! use opam_file_rs::version::OpamVersion;
+//! let mut versions = ["4.14.1", "5.0.0~beta1", "4.14.1+flambda", "v0.16.0", "5.0.0"]
+//!   .iter()
+//!   .map(|s| s.parse::<OpamVersion>().unwrap())
+//!   .collect::<Vec<_>>();
+//! versions.sort();
+//! assert_eq!(
+//!   versions.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+//!   ["4.14.1", "4.14.1+flambda", "5.0.0~beta1", "5.0.0", "v0.16.0"]
+//! );
+//! ```
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+use thiserror::Error;
+
+use super::value::RelOpKind;
+
+/// A version of a package.
+///
+/// Versions are equal if they compare equal, e.g. `1.01` and `1.1`.
+#[derive(Debug, Clone)]
+pub struct OpamVersion(String);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Error)]
+pub enum VersionError {
+  #[error("empty version")]
+  Empty,
+  /// Versions may only contain letters, digits and `-`, `+`, `.`, `_`, `~`.
+  #[error("invalid char {0:?} in version {1:?}")]
+  InvalidChar(char, String),
+}
+
+impl OpamVersion {
+  pub fn as_str(&self) -> &str {
+    &self.0
+  }
+}
+
+impl FromStr for OpamVersion {
+  type Err = VersionError;
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if s.is_empty() {
+      return Err(VersionError::Empty);
+    }
+    match s
+      .chars()
+      .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.' | '_' | '~')))
+    {
+      Some(c) => Err(VersionError::InvalidChar(c, s.to_string())),
+      None => Ok(OpamVersion(s.to_string())),
+    }
+  }
+}
+
+impl fmt::Display for OpamVersion {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl Ord for OpamVersion {
+  fn cmp(&self, other: &Self) -> Ordering {
+    compare(&self.0, &other.0)
+  }
+}
+
+impl PartialOrd for OpamVersion {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for OpamVersion {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+}
+
+impl Eq for OpamVersion {}
+
+impl Hash for OpamVersion {
+  /// Hashes the chunks with the leading zeros of the numbers removed, so that equal versions hash the same.
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    let mut str = self.0.as_bytes();
+    while !str.is_empty() {
+      let (non_digits, rest) = split_prefix(str, |c| !c.is_ascii_digit());
+      let (digits, rest) = split_prefix(rest, |c| c.is_ascii_digit());
+      non_digits.hash(state);
+      split_prefix(digits, |c| c == b'0').1.hash(state);
+      str = rest;
+    }
+  }
+}
+
+/// Whether `a <op> b` holds, where `ord` is the ordering of `a` and `b`. `=~` never holds.
+pub(crate) fn relop_holds(op: &RelOpKind, ord: Ordering) -> bool {
+  match op {
+    RelOpKind::Eq => ord == Ordering::Equal,
+    RelOpKind::Neq => ord != Ordering::Equal,
+    RelOpKind::Geq => ord != Ordering::Less,
+    RelOpKind::Gt => ord == Ordering::Greater,
+    RelOpKind::Leq => ord != Ordering::Greater,
+    RelOpKind::Lt => ord == Ordering::Less,
+    RelOpKind::Sem => false,
+  }
+}
+
+/// Compare two versions with opam's version ordering.
+pub fn compare(a: &str, b: &str) -> Ordering {
+  let (mut a, mut b) = (a.as_bytes(), b.as_bytes());
+  while !a.is_empty() || !b.is_empty() {
+    let (a_str, a_rest) = split_prefix(a, |c| !c.is_ascii_digit());
+    let (b_str, b_rest) = split_prefix(b, |c| !c.is_ascii_digit());
+    let ord = compare_non_digits(a_str, b_str);
+    if ord != Ordering::Equal {
+      return ord;
+    }
+    let (a_num, a_rest) = split_prefix(a_rest, |c| c.is_ascii_digit());
+    let (b_num, b_rest) = split_prefix(b_rest, |c| c.is_ascii_digit());
+    let ord = compare_digits(a_num, b_num);
+    if ord != Ordering::Equal {
+      return ord;
+    }
+    a = a_rest;
+    b = b_rest;
+  }
+  Ordering::Equal
+}
+
+fn split_prefix(str: &[u8], f: impl Fn(u8) -> bool) -> (&[u8], &[u8]) {
+  let len = str.iter().position(|c| !f(*c)).unwrap_or(str.len());
+  str.split_at(len)
+}
+
+/// The weight of a char in a non-digit part, `None` standing for the end of the part.
+fn order(c: Option<&u8>) -> i32 {
+  match c {
+    Some(b'~') => -1,
+    None => 0,
+    Some(c) if c.is_ascii_alphabetic() => i32::from(*c),
+    Some(c) => i32::from(*c) + 256,
+  }
+}
+
+fn compare_non_digits(a: &[u8], b: &[u8]) -> Ordering {
+  (0..a.len().max(b.len()))
+    .map(|i| order(a.get(i)).cmp(&order(b.get(i))))
+    .find(|ord| *ord != Ordering::Equal)
+    .unwrap_or(Ordering::Equal)
+}
+
+fn compare_digits(a: &[u8], b: &[u8]) -> Ordering {
+  let trim = |str: &[u8]| split_prefix(str, |c| c == b'0').1.to_vec();
+  let (a, b) = (trim(a), trim(b));
+  a.len().cmp(&b.len()).then_with(|| a.cmp(&b))
+}